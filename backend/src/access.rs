@@ -0,0 +1,143 @@
+use serde::Serialize;
+use crate::{ backup::{ Kind, Table }, html::escape, zip };
+
+// Everything held about one pupil, for a subject access request: every row in any table which refers to them,
+// values as MySQL prints them like a backup. The archive has the same records three ways: a page to read,
+// the text of their reports, and json for anyone who wants the data itself.
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; } table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #999; padding: 0.3em 0.6em; text-align: left; vertical-align: top; white-space: pre-wrap; }";
+// the columns of a subject table which aren't a term's report
+const NOT_TERMS: [&str; 2] = ["pupil_id", "pupil_name"];
+// what someone might expect to find which isn't kept anywhere, so isn't missing from the export
+const NOT_HELD: &str = "Only the current text of each report is held. Earlier versions are not kept, and there is no record of \
+who changed a report's status or when: Report_Status has the current status and a count of how many times the report has been saved.";
+
+#[derive(Debug, Serialize)]
+pub struct Access {
+    pub school: String,
+    pub pupil_id: usize,
+    pub taken: String,
+    pub taken_by: String,
+    pub not_held: &'static str,
+    pub tables: Vec<Table>,
+}
+
+// what a table holds, in words for the person reading it
+fn title(table: &Table) -> String {
+    match (table.kind, table.name.as_str()) {
+        (Kind::Class, name) => format!("Class register: {}", name.trim_start_matches("Class_")),
+        (Kind::Subject, name) => format!("Reports: {}", name),
+        (Kind::Fixed, "Pupils") => "Profile".to_string(),
+        (Kind::Fixed, "Pupil_History") => "Moves between classes and sets".to_string(),
+        (Kind::Fixed, "Report_Status") => "Report statuses, versions and comments".to_string(),
+        (Kind::Fixed, "Tutor_Comments") => "Form tutor comments".to_string(),
+        (Kind::Fixed, "Duplicates") => "Checks for reports which read almost the same as another pupil's".to_string(),
+        (Kind::Fixed, "Access_Requests") => "Subject access requests".to_string(),
+        (Kind::Fixed, name) => name.replace('_', " "),
+    }
+}
+
+impl Access {
+    pub fn new(school: String, pupil_id: usize, taken: String, taken_by: String, tables: Vec<Table>) -> Access {
+        Access { school, pupil_id, taken, taken_by, not_held: NOT_HELD, tables }
+    }
+
+    // one section a table, with a row for each record
+    pub fn html(&self) -> String {
+        let mut page = format!(r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>Records held about pupil {id}</title><style>{style}</style></head><body>
+<h1>Records held about pupil {id}</h1>
+<p>Everything {school} holds about the pupil, exported on {taken} by {by}.</p>
+<p>{not_held}</p>
+"#, id = self.pupil_id, style = STYLE, school = escape(&self.school), taken = escape(&self.taken), by = escape(&self.taken_by),
+            not_held = escape(self.not_held));
+        for table in &self.tables {
+            page.push_str(&format!("<h2>{}</h2>\n", escape(&title(table))));
+            if table.rows.is_empty() {
+                page.push_str("<p>None held.</p>\n");
+                continue;
+            }
+            let headings: String = table.columns.iter().map(|column| format!("<th>{}</th>", escape(&column.replace('_', " ")))).collect();
+            page.push_str(&format!("<table><tr>{}</tr>\n", headings));
+            for row in &table.rows {
+                let cells: String = row.iter().map(|value| format!("<td>{}</td>", escape(value.as_deref().unwrap_or_default()))).collect();
+                page.push_str(&format!("<tr>{}</tr>\n", cells));
+            }
+            page.push_str("</table>\n");
+        }
+        page.push_str("</body></html>\n");
+        page
+    }
+
+    // every report and tutor comment written about the pupil, each under its subject and term
+    pub fn text(&self) -> String {
+        let mut text = format!("Reports held about pupil {} by {}, exported on {}\n\n{}\n", self.pupil_id, self.school, self.taken, self.not_held);
+        for table in &self.tables {
+            let (heading, terms): (&str, Vec<usize>) = match (table.kind, table.name.as_str()) {
+                (Kind::Subject, name) => (name, (0..table.columns.len()).filter(|i| !NOT_TERMS.contains(&table.columns[*i].as_str())).collect()),
+                (Kind::Fixed, "Tutor_Comments") => ("Form tutor", table.columns.iter().position(|column| column == "content").into_iter().collect()),
+                _ => continue,
+            };
+            let term = table.columns.iter().position(|column| column == "term");
+            for row in &table.rows {
+                for &i in &terms {
+                    let content = if let Some(content) = row[i].as_deref().filter(|content| !content.trim().is_empty()) { content }
+                        else { continue };
+                    // subject tables have a column a term, tutor comments a row
+                    let term = term.and_then(|term| row[term].as_deref()).unwrap_or(&table.columns[i]);
+                    text.push_str(&format!("\n{}, {}\n\n{}\n", heading, term, content.trim()));
+                }
+            }
+        }
+        text
+    }
+
+    pub fn archive(&self) -> Vec<u8> {
+        let json = serde_json::to_vec_pretty(self).unwrap_or_default();
+        zip::zip(&[
+            ("records.html".to_string(), self.html().into_bytes()),
+            ("reports.txt".to_string(), self.text().into_bytes()),
+            ("records.json".to_string(), json),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Access, Kind, Table };
+
+    fn table(name: &str, kind: Kind, columns: &[&str], rows: &[&[Option<&str>]]) -> Table {
+        Table::new(
+            name.to_string(), kind,
+            columns.iter().map(|column| column.to_string()).collect(),
+            rows.iter().map(|row| row.iter().map(|value| value.map(str::to_string)).collect()).collect(),
+        )
+    }
+
+    #[test]
+    fn every_record_is_readable() {
+        let access = Access::new("Hill School".to_string(), 7, "2024-09-01 12:00:00".to_string(), "head".to_string(), vec![
+            table("Pupils", Kind::Fixed, &["id", "first_name", "last_name"], &[&[Some("7"), Some("Sam"), Some("<O'Neill>")]]),
+            table("Pupil_History", Kind::Fixed, &["pupil_id", "from_name"], &[]),
+            table("Tutor_Comments", Kind::Fixed, &["pupil_id", "term", "content"], &[&[Some("7"), Some("autumn"), Some("A settled start.")]]),
+            table("Maths", Kind::Subject, &["pupil_id", "pupil_name", "autumn", "spring"], &[&[Some("7"), Some("Sam O'Neill"), Some("Good work."), None]]),
+        ]);
+        assert!(access.html().contains("Earlier versions are not kept") && access.text().contains("Earlier versions are not kept"));
+        assert!(String::from_utf8_lossy(&serde_json::to_vec(&access).unwrap()).contains("\"not_held\":\"Only the current text"));
+
+        let html = access.html();
+        assert!(html.contains("<h2>Profile</h2>") && html.contains("<td>&lt;O&#39;Neill&gt;</td>"));
+        assert!(html.contains("<h2>Moves between classes and sets</h2>\n<p>None held.</p>"));
+        assert!(html.contains("<h2>Reports: Maths</h2>") && html.contains("<td></td>"));
+
+        let text = access.text();
+        assert!(text.contains("\nForm tutor, autumn\n\nA settled start.\n"));
+        assert!(text.contains("\nMaths, autumn\n\nGood work.\n") && !text.contains("spring"));
+
+        let archive = access.archive();
+        assert_eq!(&archive[..4], b"PK\x03\x04");
+        let end = archive.len() - 22;
+        assert_eq!(u16::from_le_bytes([archive[end + 10], archive[end + 11]]), 3);
+    }
+}
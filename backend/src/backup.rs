@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashSet};
+use serde::{Deserialize, Serialize};
+use crate::zip::crc32;
+
+// A school's whole database as one json document, for schools leaving with their data and for loading it back
+// into an empty database. Every table is copied as it is, its values the text MySQL prints them as or null,
+// so nothing is lost to a conversion and a restore needs nothing but the tables themselves.
+// Each table carries a checksum of its contents, and verify checks those and that nothing refers to a pupil who isn't there.
+
+pub const FORMAT: &str = "report-writer-backup";
+// goes up when the tables change in a way older backups can't be restored into
+pub const VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    // made by sql::DB::migrate
+    Fixed,
+    // a Class_<name> table, made with its class
+    Class,
+    // a subject's table of reports, made with the subject
+    Subject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Table {
+    pub name: String,
+    pub kind: Kind,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Option<String>>>,
+    pub checksum: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Backup {
+    pub format: String,
+    pub version: u32,
+    pub school: String,
+    pub taken: String,
+    pub tables: Vec<Table>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum BackupError {
+    Format,
+    Version { found: u32, supported: u32 },
+    Checksum { table: String },
+    // a row without a value for every column
+    Width { table: String, row: usize },
+    Repeated { table: String },
+    // a table which can't be a table name, or a class or subject table without its class or subject or the other way round
+    Name { table: String },
+    Missing { table: String },
+    Orphan { table: String, pupil_id: String },
+    // the database to restore into has its own data already
+    NotEmpty,
+    // a table's columns here aren't the backup's
+    Schema { table: String },
+    // a table restored without every row
+    Count { table: String, expected: usize, found: usize },
+}
+
+// rows in tables, by table
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub tables: BTreeMap<String, usize>,
+}
+
+// tables whose pupil_id has to be a pupil in Pupils
+const PUPIL_TABLES: [&str; 4] = ["Report_Status", "Pupil_History", "Tutor_Comments", "Duplicates"];
+
+fn checksum(columns: &[String], rows: &[Vec<Option<String>>]) -> String {
+    format!("{:08x}", crc32(&serde_json::to_vec(&(columns, rows)).unwrap_or_default()))
+}
+
+// the same rule lib has for names which become tables
+fn table_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 30 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Table {
+    pub fn new(name: String, kind: Kind, columns: Vec<String>, rows: Vec<Vec<Option<String>>>) -> Table {
+        let checksum = checksum(&columns, &rows);
+        Table { name, kind, columns, rows, checksum }
+    }
+
+    // the values in a column, none if there's no such column
+    pub fn column(&self, column: &str) -> impl Iterator<Item = &str> {
+        let i = self.columns.iter().position(|name| name == column);
+        self.rows.iter().filter_map(move |row| i.and_then(|i| row.get(i)?.as_deref()))
+    }
+}
+
+impl Backup {
+    pub fn new(school: String, taken: String, tables: Vec<Table>) -> Backup {
+        Backup { format: FORMAT.to_string(), version: VERSION, school, taken, tables }
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary { tables: self.tables.iter().map(|table| (table.name.clone(), table.rows.len())).collect() }
+    }
+
+    // Everything which can be checked without a database: the format and version, each table's checksum and shape,
+    // that class and subject tables match Classes and Subjects, and that every pupil_id is a pupil
+    pub fn verify(&self) -> Result<(), BackupError> {
+        if self.format != FORMAT { return Err(BackupError::Format) }
+        if self.version != VERSION { return Err(BackupError::Version { found: self.version, supported: VERSION }) }
+
+        let mut names = HashSet::new();
+        for table in &self.tables {
+            if !table_name(&table.name) || !table.columns.iter().all(|column| table_name(column)) {
+                return Err(BackupError::Name { table: table.name.clone() });
+            }
+            if !names.insert(table.name.to_lowercase()) { return Err(BackupError::Repeated { table: table.name.clone() }) }
+            if let Some(row) = table.rows.iter().position(|row| row.len() != table.columns.len()) {
+                return Err(BackupError::Width { table: table.name.clone(), row });
+            }
+            if checksum(&table.columns, &table.rows) != table.checksum { return Err(BackupError::Checksum { table: table.name.clone() }) }
+        }
+
+        let missing = |name: &str| BackupError::Missing { table: name.to_string() };
+        let pupils: HashSet<&str> = self.table("Pupils").ok_or(missing("Pupils"))?.column("id").collect();
+        let classes: HashSet<String> = self.table("Classes").ok_or(missing("Classes"))?.column("class_name").map(|class| format!("Class_{}", class)).collect();
+        let subjects: HashSet<&str> = self.table("Subjects").ok_or(missing("Subjects"))?.column("name").collect();
+        for table in &self.tables {
+            let listed = match table.kind {
+                Kind::Class => classes.contains(&table.name),
+                Kind::Subject => subjects.contains(table.name.as_str()),
+                Kind::Fixed => true,
+            };
+            if !listed { return Err(BackupError::Name { table: table.name.clone() }) }
+        }
+        for name in classes.iter().map(String::as_str).chain(subjects.iter().copied()) {
+            if self.table(name).is_none() { return Err(missing(name)) }
+        }
+
+        let mut referring = self.tables.iter().filter(|table| table.kind != Kind::Fixed || PUPIL_TABLES.contains(&table.name.as_str()));
+        referring.try_for_each(|table| {
+            let columns = if table.name == "Duplicates" { vec!["pupil_a", "pupil_b"] } else { vec!["pupil_id"] };
+            match columns.into_iter().flat_map(|column| table.column(column)).find(|id| !pupils.contains(id)) {
+                Some(id) => Err(BackupError::Orphan { table: table.name.clone(), pupil_id: id.to_string() }),
+                None => Ok(()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Backup, BackupError, Kind, Table, VERSION };
+
+    fn text(values: &[&str]) -> Vec<Option<String>> {
+        values.iter().map(|value| if *value == "NULL" { None } else { Some(value.to_string()) }).collect()
+    }
+
+    fn backup() -> Backup {
+        let table = |name: &str, kind, columns: &[&str], rows: Vec<Vec<Option<String>>>| {
+            Table::new(name.to_string(), kind, columns.iter().map(|column| column.to_string()).collect(), rows)
+        };
+        Backup::new("Hill School".to_string(), "2024-09-01 12:00:00".to_string(), vec![
+            table("Pupils", Kind::Fixed, &["id", "first_name", "last_name", "birthdate", "class", "preferred_name", "pronouns"],
+                vec![text(&["1", "Sam", "O'Neill", "2011-03-04", "9A", "NULL", "they/them"]), text(&["2", "Zoë", "Lee", "2011-05-06", "", "NULL", "NULL"])]),
+            table("Classes", Kind::Fixed, &["class_name"], vec![text(&["9A"])]),
+            table("Subjects", Kind::Fixed, &["name"], vec![text(&["Maths"])]),
+            table("Report_Status", Kind::Fixed, &["pupil_id", "subject", "term", "status"], vec![text(&["1", "Maths", "autumn", "submitted"])]),
+            table("Class_9A", Kind::Class, &["pupil_id", "name"], vec![text(&["1", "Sam O'Neill"])]),
+            table("Maths", Kind::Subject, &["pupil_id", "pupil_name", "autumn"], vec![text(&["1", "Sam O'Neill", "Good work,\n\"well done\"."])]),
+        ])
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let backup = backup();
+        assert_eq!(backup.verify(), Ok(()));
+        let json = serde_json::to_string(&backup).unwrap();
+        let restored: Backup = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, backup);
+        assert_eq!(restored.verify(), Ok(()));
+        assert_eq!(restored.summary().tables.get("Pupils"), Some(&2));
+    }
+
+    #[test]
+    fn damage_is_found() {
+        let mut changed = backup();
+        changed.tables[5].rows[0][2] = Some("Changed".to_string());
+        assert_eq!(changed.verify(), Err(BackupError::Checksum { table: "Maths".to_string() }));
+
+        let mut newer = backup();
+        newer.version += 1;
+        assert_eq!(newer.verify(), Err(BackupError::Version { found: VERSION + 1, supported: VERSION }));
+
+        let mut orphan = backup();
+        orphan.tables[4] = Table::new("Class_9A".to_string(), Kind::Class, vec!["pupil_id".to_string()], vec![text(&["3"])]);
+        assert_eq!(orphan.verify(), Err(BackupError::Orphan { table: "Class_9A".to_string(), pupil_id: "3".to_string() }));
+
+        let mut missing = backup();
+        missing.tables.remove(4);
+        assert_eq!(missing.verify(), Err(BackupError::Missing { table: "Class_9A".to_string() }));
+
+        let mut injected = backup();
+        injected.tables[5].name = "Maths; drop table Pupils".to_string();
+        assert!(matches!(injected.verify(), Err(BackupError::Name { .. })));
+    }
+}
@@ -0,0 +1,323 @@
+use serde::{Deserialize, Serialize};
+use crate::{
+    pdf::{self, Document, Font},
+    template::capitalise,
+};
+
+// line height as a multiple of the font size
+const LEADING: f32 = 1.35;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Paper {
+    #[default]
+    A4,
+    Letter,
+}
+
+// How a school's report cards look. Every field can be left out of the json for its default.
+// Sizes are in points, 72 to an inch.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Layout {
+    pub paper: Paper,
+    pub margin: f32,
+    pub font_size: f32,
+    pub heading_size: f32,
+    // printed at the top of every card, the school's name when there isn't one
+    pub title: Option<String>,
+    pub address: Vec<String>,
+    pub show_class: bool,
+    pub show_birthdate: bool,
+    pub tutor_heading: String,
+    // at the foot of every page, beside the page number
+    pub footer: Option<String>,
+    // subjects printed first in this order, the rest follow alphabetically
+    pub subject_order: Vec<String>,
+}
+
+// Text which Helvetica can't print, and whose card it's on: none for the school's name and layout.
+// Cards with any are left out rather than printed with ? in place of a name, see pdf::unprintable.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Unprintable {
+    pub pupil_id: Option<usize>,
+    pub found: String,
+}
+
+// Everything printed on one pupil's card for a term. reports are subject and content, written ones only.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Card {
+    pub pupil_id: usize,
+    pub name: String,
+    pub class: String,
+    pub birthdate: String,
+    pub term: String,
+    pub year: Option<u16>,
+    pub tutors: Vec<String>,
+    pub tutor_comment: Option<String>,
+    pub reports: Vec<(String, String)>,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout {
+            paper: Paper::A4,
+            margin: 50.0,
+            font_size: 10.0,
+            heading_size: 16.0,
+            title: None,
+            address: vec![],
+            show_class: true,
+            show_birthdate: true,
+            tutor_heading: "Tutor's comment".to_string(),
+            footer: None,
+            subject_order: vec![],
+        }
+    }
+}
+
+impl Layout {
+    // sizes which leave room on the page for a report
+    pub fn valid(&self) -> bool {
+        (20.0..=100.0).contains(&self.margin)
+            && (7.0..=16.0).contains(&self.font_size)
+            && (self.font_size..=32.0).contains(&self.heading_size)
+            && self.address.len() <= 6
+            && !self.tutor_heading.trim().is_empty()
+    }
+
+    fn paper(&self) -> (f32, f32) {
+        match self.paper {
+            Paper::A4 => pdf::A4,
+            Paper::Letter => pdf::LETTER,
+        }
+    }
+}
+
+impl Card {
+    fn text(&self) -> impl Iterator<Item = &str> {
+        [self.name.as_str(), &self.class].into_iter()
+            .chain(self.tutors.iter().map(String::as_str))
+            .chain(self.tutor_comment.as_deref())
+            .chain(self.reports.iter().flat_map(|(subject, content)| [subject.as_str(), content]))
+    }
+
+    // what the card is called in a zip of a class's cards, made safe for any file system
+    pub fn file_name(&self, extension: &str) -> String {
+        let name: String = self.name.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect();
+        format!("{}_{}_{}.{}", name, self.term, self.pupil_id, extension)
+    }
+
+    // the subjects in the layout's order then alphabetical, with _ in names printed as spaces
+    fn reports(&self, layout: &Layout) -> Vec<(String, &str)> {
+        let mut reports: Vec<&(String, String)> = self.reports.iter().filter(|(_, content)| !content.trim().is_empty()).collect();
+        reports.sort_by_key(|(subject, _)| {
+            let position = layout.subject_order.iter().position(|ordered| ordered.eq_ignore_ascii_case(subject));
+            (position.unwrap_or(usize::MAX), subject.to_lowercase())
+        });
+        reports.into_iter().map(|(subject, content)| (subject.replace('_', " "), content.as_str())).collect()
+    }
+}
+
+// Lines of the text no wider than width, broken at spaces where possible and at line breaks always
+pub fn wrap(text: &str, font: Font, size: f32, width: f32) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let joined = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+            if pdf::width(&joined, font, size) <= width {
+                line = joined;
+                continue
+            }
+            if !line.is_empty() { lines.push(std::mem::take(&mut line)) }
+            // a word too long for a line of its own is split wherever it has to be
+            for c in word.chars() {
+                if !line.is_empty() && pdf::width(&format!("{}{}", line, c), font, size) > width { lines.push(std::mem::take(&mut line)) }
+                line.push(c);
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// Writes cards into a document, moving to a new page when one is full
+struct Writer<'a> {
+    document: Document,
+    layout: &'a Layout,
+    // the baseline of the last line written
+    y: f32,
+}
+
+impl Writer<'_> {
+    fn line_height(&self, size: f32) -> f32 {
+        size * LEADING
+    }
+
+    fn new_page(&mut self) {
+        self.document.page();
+        self.y = self.document.height() - self.layout.margin;
+    }
+
+    // room for this much more above the footer, or a new page
+    fn room(&mut self, height: f32) {
+        if self.y - height < self.layout.margin + self.line_height(self.layout.font_size) { self.new_page() }
+    }
+
+    fn text(&mut self, font: Font, size: f32, text: &str) {
+        let width = self.document.width() - 2.0 * self.layout.margin;
+        for line in wrap(text, font, size, width) {
+            self.room(self.line_height(size));
+            self.y -= self.line_height(size);
+            self.document.text(self.layout.margin, self.y, font, size, &line);
+        }
+    }
+
+    fn gap(&mut self, lines: f32) {
+        self.y -= self.line_height(self.layout.font_size) * lines;
+    }
+
+    fn rule(&mut self) {
+        self.gap(0.5);
+        let (left, right) = (self.layout.margin, self.document.width() - self.layout.margin);
+        self.document.line((left, self.y), (right, self.y), 0.5);
+        self.gap(0.25);
+    }
+
+    // A card starts on a new page: the school, who it's for, each subject's report and the tutor's comment
+    fn card(&mut self, card: &Card, school: &str) {
+        let layout = self.layout;
+        let (size, heading) = (layout.font_size, layout.heading_size);
+        self.new_page();
+        let first = self.document.pages() - 1;
+
+        self.text(Font::Bold, heading, layout.title.as_deref().unwrap_or(school));
+        for line in &layout.address {
+            self.text(Font::Regular, size, line);
+        }
+        self.rule();
+
+        let term = match card.year {
+            Some(year) => format!("{} {}", capitalise(&card.term), year),
+            None => capitalise(&card.term),
+        };
+        self.text(Font::Bold, size * 1.2, &format!("Report: {}", term));
+        self.text(Font::Regular, size, &format!("Name: {}", card.name));
+        if layout.show_class && !card.class.is_empty() { self.text(Font::Regular, size, &format!("Class: {}", card.class)) }
+        if layout.show_birthdate { self.text(Font::Regular, size, &format!("Date of birth: {}", card.birthdate)) }
+        if !card.tutors.is_empty() { self.text(Font::Regular, size, &format!("Tutor: {}", card.tutors.join(", "))) }
+        self.rule();
+
+        let reports = card.reports(layout);
+        if reports.is_empty() { self.text(Font::Regular, size, "No reports have been written for this term.") }
+        for (subject, content) in reports {
+            // a subject's heading stays with the start of its report
+            self.room(self.line_height(size) * 3.0);
+            self.gap(0.5);
+            self.text(Font::Bold, size * 1.1, &subject);
+            self.text(Font::Regular, size, content);
+        }
+
+        if let Some(comment) = card.tutor_comment.as_deref().filter(|comment| !comment.trim().is_empty()) {
+            self.rule();
+            self.room(self.line_height(size) * 3.0);
+            self.text(Font::Bold, size * 1.1, &layout.tutor_heading);
+            self.text(Font::Regular, size, comment);
+        }
+
+        // page numbers count the card's pages, as each card is handed out on its own
+        let last = self.document.pages();
+        for page in first..last {
+            self.document.turn(page);
+            let y = layout.margin;
+            if let Some(footer) = &layout.footer { self.document.text(layout.margin, y, Font::Regular, size * 0.8, footer) }
+            let number = format!("{}, page {} of {}", card.name, page - first + 1, last - first);
+            let x = self.document.width() - layout.margin - pdf::width(&number, Font::Regular, size * 0.8);
+            self.document.text(x, y, Font::Regular, size * 0.8, &number);
+        }
+        self.document.turn(last - 1);
+    }
+}
+
+// Whatever in the cards, the layout or the school's name can't be printed, nothing when they all can
+pub fn unprintable(cards: &[Card], layout: &Layout, school: &str) -> Vec<Unprintable> {
+    let found = |text: &mut dyn Iterator<Item = &str>| text.flat_map(pdf::unprintable).collect::<std::collections::BTreeSet<char>>().into_iter().collect::<String>();
+    let mut school = [school, &layout.tutor_heading].into_iter()
+        .chain(layout.title.as_deref()).chain(layout.footer.as_deref()).chain(layout.address.iter().map(String::as_str));
+    let school = Some(found(&mut school)).filter(|found| !found.is_empty()).map(|found| Unprintable { pupil_id: None, found });
+    school.into_iter().chain(cards.iter().filter_map(|card| {
+        Some(found(&mut card.text())).filter(|found| !found.is_empty()).map(|found| Unprintable { pupil_id: Some(card.pupil_id), found })
+    })).collect()
+}
+
+// One pdf of the cards, each starting on a new page
+pub fn render(cards: &[Card], layout: &Layout, school: &str) -> Vec<u8> {
+    let mut writer = Writer { document: Document::new(layout.paper()), layout, y: 0.0 };
+    for card in cards {
+        writer.card(card, school);
+    }
+    writer.document.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ render, unprintable, wrap, Card, Layout, Unprintable };
+    use crate::pdf::{ self, Font };
+
+    fn card(reports: usize) -> Card {
+        Card {
+            pupil_id: 7,
+            name: "Sam O'Neill".to_string(),
+            class: "9A".to_string(),
+            birthdate: "2011-03-04".to_string(),
+            term: "autumn".to_string(),
+            year: Some(2024),
+            tutors: vec!["Ms Patel".to_string()],
+            tutor_comment: Some("A good term.".to_string()),
+            reports: (0..reports).map(|i| (format!("Subject_{}", i), "Sam has worked hard this term. ".repeat(20))).collect(),
+        }
+    }
+
+    #[test]
+    fn unprintable_names_are_found() {
+        let mut polish = card(1);
+        polish.name = "Łukasz Nowak".to_string();
+        polish.reports[0].1 = "Łukasz has made a great start, well done.\n“Excellent”.".to_string();
+        assert_eq!(unprintable(&[card(1), polish], &Layout::default(), "Hill School"), vec![Unprintable { pupil_id: Some(7), found: "Ł".to_string() }]);
+        assert_eq!(unprintable(&[card(1)], &Layout::default(), "Szkoła"), vec![Unprintable { pupil_id: None, found: "ł".to_string() }]);
+        assert!(unprintable(&[card(2)], &Layout::default(), "Zoë's School").is_empty());
+    }
+
+    #[test]
+    fn lines_fit() {
+        let text = "Sam has worked hard this term.\nSupercalifragilisticexpialidocious";
+        let lines = wrap(text, Font::Regular, 10.0, 60.0);
+        assert!(lines.iter().all(|line| pdf::width(line, Font::Regular, 10.0) <= 60.0));
+        assert_eq!(lines.join(" ").split_whitespace().collect::<String>(), text.split_whitespace().collect::<String>());
+        assert_eq!(lines[0], "Sam has");
+        assert!(wrap("", Font::Regular, 10.0, 60.0).is_empty());
+    }
+
+    #[test]
+    fn long_cards_take_more_pages() {
+        let layout = Layout { footer: Some("Printed for parents".to_string()), ..Layout::default() };
+        let pages = |pdf: Vec<u8>| String::from_utf8_lossy(&pdf).matches("/Type /Page ").count();
+        assert_eq!(pages(render(&[card(1)], &layout, "Hill School")), 1);
+        assert!(pages(render(&[card(12)], &layout, "Hill School")) > 1);
+        assert_eq!(pages(render(&[card(1), card(0)], &layout, "Hill School")), 2);
+        let text = String::from_utf8_lossy(&render(&[card(1)], &layout, "Hill School")).to_string();
+        assert!(text.contains("(Hill School)") && text.contains("(Subject 0)") && text.contains("page 1 of 1"));
+    }
+
+    #[test]
+    fn subjects_in_the_schools_order() {
+        let mut card = card(0);
+        card.reports = vec![("Art".to_string(), "Good.".to_string()), ("Maths".to_string(), "Fine.".to_string()), ("English".to_string(), " ".to_string())];
+        let layout = Layout { subject_order: vec!["maths".to_string()], ..Layout::default() };
+        assert_eq!(card.reports(&layout), vec![("Maths".to_string(), "Fine."), ("Art".to_string(), "Good.")]);
+        assert_eq!(card.file_name("pdf"), "Sam_O_Neill_autumn_7.pdf");
+        assert!(layout.valid());
+        assert!(!Layout { margin: 5.0, ..Layout::default() }.valid());
+    }
+}
@@ -0,0 +1,194 @@
+use std::str::FromStr;
+use crate::zip;
+
+// Spreadsheets of reports for the office, one row at a time so a year group's never all in memory.
+// CSV starts with a byte order mark, without which Excel reads UTF-8 as the local code page.
+// XLSX is the smallest workbook Excel opens: one sheet of inline strings, so there's no shared string table
+// to build before the rows can be written, in an archive streamed file by file.
+
+const BOM: &str = "\u{feff}";
+const MAIN: &str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const RELATIONSHIPS: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/></Types>"#;
+const ROOT_RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+const WORKBOOK_RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/></Relationships>"#;
+// cell styles: 1 is the bold heading, 2 wraps reports at their line breaks
+const STYLES: &str = r#"<fonts count="2"><font><sz val="11"/><name val="Calibri"/></font><font><b/><sz val="11"/><name val="Calibri"/></font></fonts><fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills><borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders><cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs><cellXfs count="3"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/><xf numFmtId="0" fontId="1" fillId="0" borderId="0" xfId="0" applyFont="1"/><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0" applyAlignment="1"><alignment wrapText="1" vertical="top"/></xf></cellXfs>"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Csv,
+    Xlsx,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(format: &str) -> Result<Format, ()> {
+        match format {
+            "csv" => Ok(Format::Csv),
+            "xlsx" => Ok(Format::Xlsx),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Format {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Xlsx => "xlsx",
+        }
+    }
+}
+
+// A field quoted when it has to be, with the line breaks in reports kept inside the quotes. One which a
+// spreadsheet would take for a formula starts with ' so it's shown as the text it is.
+pub fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) { format!("'{}", field) } else { field.to_string() };
+    if field.contains([',', '"', '\n', '\r']) { format!("\"{}\"", field.replace('"', "\"\"")) } else { field }
+}
+
+pub fn csv_row(fields: &[String]) -> String {
+    let mut row = fields.iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(",");
+    row.push_str("\r\n");
+    row
+}
+
+// text escaped for xml, without the control characters xml can't hold at all
+fn xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.replace("\r\n", "\n").chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' => escaped.push(c),
+            c if c.is_control() => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// A, B, ... Z, AA, AB for the column counting from 0
+fn column(mut i: usize) -> String {
+    let mut letters = vec![];
+    loop {
+        letters.push((b'A' + (i % 26) as u8) as char);
+        if i < 26 { break }
+        i = i / 26 - 1;
+    }
+    letters.into_iter().rev().collect()
+}
+
+// A spreadsheet being written: start with the headings, a row for each pupil, then finish, sending each piece as it comes
+#[derive(Debug)]
+pub struct Sheet {
+    format: Format,
+    archive: zip::Stream,
+    rows: usize,
+}
+
+impl Sheet {
+    pub fn new(format: Format) -> Sheet {
+        Sheet { format, archive: zip::Stream::new(), rows: 0 }
+    }
+
+    fn xlsx_row(&mut self, fields: &[String], style: u8) -> Vec<u8> {
+        self.rows += 1;
+        let cells: String = fields.iter().enumerate()
+            .map(|(i, field)| format!(r#"<c r="{}{}" t="inlineStr" s="{}"><is><t xml:space="preserve">{}</t></is></c>"#, column(i), self.rows, style, xml(field)))
+            .collect();
+        let row = format!(r#"<row r="{}">{}</row>"#, self.rows, cells);
+        self.archive.write(row.as_bytes())
+    }
+
+    pub fn start(&mut self, headings: &[String]) -> Vec<u8> {
+        match self.format {
+            Format::Csv => format!("{}{}", BOM, csv_row(headings)).into_bytes(),
+            Format::Xlsx => {
+                let workbook = format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="{}" xmlns:r="{}"><sheets><sheet name="Reports" sheetId="1" r:id="rId1"/></sheets></workbook>"#, MAIN, RELATIONSHIPS);
+                let styles = format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<styleSheet xmlns="{}">{}</styleSheet>"#, MAIN, STYLES);
+                let mut bytes = self.archive.file("[Content_Types].xml", CONTENT_TYPES.as_bytes());
+                bytes.extend(self.archive.file("_rels/.rels", ROOT_RELATIONSHIPS.as_bytes()));
+                bytes.extend(self.archive.file("xl/workbook.xml", workbook.as_bytes()));
+                bytes.extend(self.archive.file("xl/_rels/workbook.xml.rels", WORKBOOK_RELATIONSHIPS.as_bytes()));
+                bytes.extend(self.archive.file("xl/styles.xml", styles.as_bytes()));
+                bytes.extend(self.archive.start("xl/worksheets/sheet1.xml"));
+                let head = format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="{}"><sheetViews><sheetView workbookViewId="0"><pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/></sheetView></sheetViews><sheetData>"#, MAIN);
+                bytes.extend(self.archive.write(head.as_bytes()));
+                bytes.extend(self.xlsx_row(headings, 1));
+                bytes
+            },
+        }
+    }
+
+    pub fn row(&mut self, fields: &[String]) -> Vec<u8> {
+        match self.format {
+            Format::Csv => csv_row(fields).into_bytes(),
+            Format::Xlsx => self.xlsx_row(fields, 2),
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        match self.format {
+            Format::Csv => vec![],
+            Format::Xlsx => {
+                let mut bytes = self.archive.write(b"</sheetData></worksheet>");
+                bytes.extend(self.archive.finish());
+                bytes
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ column, csv_row, xml, Format, Sheet };
+
+    #[test]
+    fn csv_quotes_what_it_must() {
+        let row = csv_row(&["7".to_string(), "O'Neill, Sam".to_string(), "Said \"well done\".\nGood term.".to_string()]);
+        assert_eq!(row, "7,\"O'Neill, Sam\",\"Said \"\"well done\"\".\nGood term.\"\r\n");
+        let row = csv_row(&["=HYPERLINK(\"x\")".to_string(), "+1".to_string(), "-2".to_string(), "@SUM(A1)".to_string(), "a=b".to_string()]);
+        assert_eq!(row, "\"'=HYPERLINK(\"\"x\"\")\",'+1,'-2,'@SUM(A1),a=b\r\n");
+        let mut sheet = Sheet::new(Format::Csv);
+        assert_eq!(sheet.start(&["Id".to_string()]), "\u{feff}Id\r\n".as_bytes());
+        assert_eq!(sheet.row(&["Zoë".to_string()]), "Zoë\r\n".as_bytes());
+        assert!(sheet.finish().is_empty());
+    }
+
+    #[test]
+    fn xlsx_is_an_archive_of_the_sheet() {
+        assert_eq!((column(0), column(25), column(26), column(27 * 26)), ("A".to_string(), "Z".to_string(), "AA".to_string(), "AAA".to_string()));
+        assert_eq!(xml("a < b & \u{1}c\r\nd"), "a &lt; b &amp; c\nd");
+
+        let mut sheet = Sheet::new(Format::Xlsx);
+        let mut bytes = sheet.start(&["Id".to_string(), "Maths Autumn".to_string()]);
+        bytes.extend(sheet.row(&["7".to_string(), "Good <work>.".to_string()]));
+        bytes.extend(sheet.finish());
+        assert_eq!(&bytes[..4], b"PK\x03\x04");
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.contains("[Content_Types].xml") && text.contains("xl/worksheets/sheet1.xml"));
+        assert!(text.contains(r#"<c r="B2" t="inlineStr" s="2"><is><t xml:space="preserve">Good &lt;work&gt;.</t></is></c></row></sheetData></worksheet>"#));
+        // content types, two rels, workbook, styles and the sheet
+        let end = bytes.len() - 22;
+        assert_eq!(u16::from_le_bytes([bytes[end + 10], bytes[end + 11]]), 6);
+    }
+}
@@ -0,0 +1,529 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::card::Card;
+
+// HTML report card templates, a small part of Jinja:
+//     {{ pupil.name }}, {{ tutor_comment | default("None this term") }}, {{ report.content | paragraphs }}
+//     {% for report in reports %}...{% endfor %}, {% if tutor_comment %}...{% else %}...{% endif %}, {# comments #}
+// Templates only see the card they're given, see context, and everything printed is HTML escaped.
+// There are no includes, no way to call anything and no loops except over the card's lists,
+// so a template can't read anything else. Loops can only be two deep and rendering stops after MAX_STEPS nodes,
+// so it can't run for long either, even printing nothing. Offsets in errors are char offsets into the template.
+
+// the longest template which can be saved, and the most a template can print for one pupil
+pub const MAX_TEMPLATE: usize = 50_000;
+const MAX_OUTPUT: usize = 1_000_000;
+// how deep ifs and fors can be inside each other, and fors inside fors
+const MAX_DEPTH: usize = 8;
+const MAX_LOOPS: usize = 2;
+// the most nodes rendering one pupil's card can go through, loops counting each time round
+const MAX_STEPS: usize = 100_000;
+// the names a template can start a variable with, besides those its loops make
+const ROOTS: [&str; 7] = ["school", "term", "year", "pupil", "tutors", "tutor_comment", "reports"];
+const FILTERS: [&str; 7] = ["upper", "lower", "capitalize", "length", "default", "join", "paragraphs"];
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum HtmlError {
+    TooLong { max: usize },
+    // a {{, {% or {# without its end, or an if or for without its endif or endfor
+    Unclosed { at: usize },
+    // a tag which makes no sense where it is: endif with no if, an else in a for, or one that isn't a tag at all
+    Unexpected { at: usize, found: String },
+    UnknownVariable { at: usize, name: String },
+    UnknownFilter { at: usize, name: String },
+    TooDeep { at: usize },
+    // what was printed went past MAX_OUTPUT
+    TooBig,
+    // rendering went past MAX_STEPS
+    TooSlow,
+}
+
+// A pupil's card for a term through a template, the school's saved one when it's left out
+#[derive(Debug, Deserialize)]
+pub struct Preview {
+    pub pupil_id: usize,
+    pub term: String,
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Path {
+    at: usize,
+    parts: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Expr {
+    path: Path,
+    filters: Vec<(String, Option<String>)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Test {
+    Truthy(Expr),
+    Not(Expr),
+    Equals(Expr, String),
+    NotEquals(Expr, String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Output(Expr),
+    If { test: Test, then: Vec<Node>, otherwise: Vec<Node> },
+    For { name: String, list: Path, body: Vec<Node> },
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Output { at: usize, inner: String },
+    Tag { at: usize, inner: String },
+}
+
+fn tokens(template: &str) -> Result<Vec<Token>, HtmlError> {
+    let chars: Vec<char> = template.chars().collect();
+    let mut tokens = vec![];
+    let mut text = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let close = match (chars[i], chars.get(i + 1)) {
+            ('{', Some('{')) => "}}",
+            ('{', Some('%')) => "%}",
+            ('{', Some('#')) => "#}",
+            (c, _) => {
+                text.push(c);
+                i += 1;
+                continue
+            },
+        };
+        let close: Vec<char> = close.chars().collect();
+        let end = (i + 2..chars.len().saturating_sub(1)).find(|j| chars[*j..*j + 2] == close[..]).ok_or(HtmlError::Unclosed { at: i })?;
+        if !text.is_empty() { tokens.push(Token::Text(std::mem::take(&mut text))) }
+        let inner: String = chars[i + 2..end].iter().collect::<String>().trim().to_string();
+        match close[0] {
+            '}' => tokens.push(Token::Output { at: i, inner }),
+            '%' => tokens.push(Token::Tag { at: i, inner }),
+            _ => (),
+        }
+        i = end + 2;
+    }
+    if !text.is_empty() { tokens.push(Token::Text(text)) }
+    Ok(tokens)
+}
+
+fn identifier(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_lowercase() || c == '_') && word.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+// a "quoted" or 'quoted' string
+fn string(text: &str) -> Option<String> {
+    let text = text.trim();
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let inner = text.strip_prefix(quote)?.strip_suffix(quote)?;
+    if inner.contains(quote) { return None }
+    Some(inner.to_string())
+}
+
+// Parses tokens into nodes, checking every variable is one the template can see
+struct Parser {
+    tokens: std::vec::IntoIter<Token>,
+    // the names loops have made, innermost last
+    scope: Vec<String>,
+    errors: Vec<HtmlError>,
+}
+
+impl Parser {
+    fn path(&mut self, text: &str, at: usize) -> Path {
+        let parts: Vec<String> = text.trim().split('.').map(str::to_string).collect();
+        if !parts.iter().all(|part| identifier(part)) {
+            self.errors.push(HtmlError::Unexpected { at, found: text.trim().to_string() });
+        } else if !ROOTS.contains(&parts[0].as_str()) && !self.scope.contains(&parts[0]) {
+            self.errors.push(HtmlError::UnknownVariable { at, name: parts[0].clone() });
+        }
+        Path { at, parts }
+    }
+
+    // path | filter | filter("argument"), with paragraphs only last as it makes HTML
+    fn expr(&mut self, text: &str, at: usize) -> Expr {
+        let mut pieces = split_outside_quotes(text, '|').into_iter();
+        let path = self.path(&pieces.next().unwrap_or_default(), at);
+        let mut filters = vec![];
+        for piece in pieces {
+            let piece = piece.trim();
+            let (name, argument) = match piece.split_once('(') {
+                Some((name, rest)) => match rest.strip_suffix(')').and_then(string) {
+                    Some(argument) => (name.trim(), Some(argument)),
+                    None => {
+                        self.errors.push(HtmlError::Unexpected { at, found: piece.to_string() });
+                        continue
+                    },
+                },
+                None => (piece, None),
+            };
+            if !FILTERS.contains(&name) { self.errors.push(HtmlError::UnknownFilter { at, name: name.to_string() }) }
+            filters.push((name.to_string(), argument));
+        }
+        if filters.iter().rev().skip(1).any(|(name, _)| name == "paragraphs") {
+            self.errors.push(HtmlError::Unexpected { at, found: "paragraphs".to_string() });
+        }
+        Expr { path, filters }
+    }
+
+    fn test(&mut self, text: &str, at: usize) -> Test {
+        let text = text.trim();
+        if let Some(rest) = text.strip_prefix("not ") { return Test::Not(self.expr(rest, at)) }
+        for (operator, equals) in [("==", true), ("!=", false)] {
+            // the left is a variable and its filters, so the first operator without a quote before it is the one
+            if let Some((left, right)) = text.split_once(operator).filter(|(left, _)| !left.contains(['"', '\''])) {
+                let expr = self.expr(left, at);
+                let value = string(right).unwrap_or_else(|| {
+                    self.errors.push(HtmlError::Unexpected { at, found: right.trim().to_string() });
+                    String::new()
+                });
+                return if equals { Test::Equals(expr, value) } else { Test::NotEquals(expr, value) };
+            }
+        }
+        Test::Truthy(self.expr(text, at))
+    }
+
+    // Nodes up to one of the tags in end, returning which it was. At the top level end is empty and the tokens run out.
+    fn nodes(&mut self, end: &[&str], depth: usize, opened: usize) -> (Vec<Node>, Option<String>) {
+        let mut nodes = vec![];
+        while let Some(token) = self.tokens.next() {
+            let (at, inner) = match token {
+                Token::Text(text) => {
+                    nodes.push(Node::Text(text));
+                    continue
+                },
+                Token::Output { at, inner } => {
+                    let expr = self.expr(&inner, at);
+                    nodes.push(Node::Output(expr));
+                    continue
+                },
+                Token::Tag { at, inner } => (at, inner),
+            };
+            let (keyword, rest) = inner.split_once(char::is_whitespace).unwrap_or((&inner, ""));
+            if end.contains(&keyword) && rest.trim().is_empty() { return (nodes, Some(keyword.to_string())) }
+            // every for puts its name and loop in scope
+            let loops = self.scope.len() / 2;
+            if (depth >= MAX_DEPTH && (keyword == "if" || keyword == "for")) || (loops >= MAX_LOOPS && keyword == "for") {
+                self.errors.push(HtmlError::TooDeep { at });
+                return (nodes, None);
+            }
+            match keyword {
+                "if" => {
+                    let test = self.test(rest, at);
+                    let (then, closed) = self.nodes(&["else", "endif"], depth + 1, at);
+                    let otherwise = if closed.as_deref() == Some("else") { self.nodes(&["endif"], depth + 1, at).0 } else { vec![] };
+                    nodes.push(Node::If { test, then, otherwise });
+                },
+                "for" => {
+                    let words: Vec<&str> = rest.split_whitespace().collect();
+                    let (name, list) = match words.as_slice() {
+                        [name, "in", list] if identifier(name) && !ROOTS.contains(name) => (name.to_string(), self.path(list, at)),
+                        _ => {
+                            self.errors.push(HtmlError::Unexpected { at, found: inner.clone() });
+                            (String::new(), Path { at, parts: vec![] })
+                        },
+                    };
+                    self.scope.extend([name.clone(), "loop".to_string()]);
+                    let (body, _) = self.nodes(&["endfor"], depth + 1, at);
+                    self.scope.truncate(self.scope.len() - 2);
+                    nodes.push(Node::For { name, list, body });
+                },
+                _ => self.errors.push(HtmlError::Unexpected { at, found: inner.clone() }),
+            }
+        }
+        if !end.is_empty() { self.errors.push(HtmlError::Unclosed { at: opened }) }
+        (nodes, None)
+    }
+}
+
+fn split_outside_quotes(text: &str, separator: char) -> Vec<String> {
+    let mut pieces = vec![String::new()];
+    let mut quote = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, c) if c == separator => {
+                pieces.push(String::new());
+                continue
+            },
+            _ => (),
+        }
+        if let Some(piece) = pieces.last_mut() { piece.push(c) }
+    }
+    pieces
+}
+
+fn parse(template: &str) -> Result<Vec<Node>, Vec<HtmlError>> {
+    if template.chars().count() > MAX_TEMPLATE { return Err(vec![HtmlError::TooLong { max: MAX_TEMPLATE }]) }
+    let tokens = tokens(template).map_err(|err| vec![err])?;
+    let mut parser = Parser { tokens: tokens.into_iter(), scope: vec![], errors: vec![] };
+    let (nodes, _) = parser.nodes(&[], 0, 0);
+    if parser.errors.is_empty() { Ok(nodes) } else { Err(parser.errors) }
+}
+
+// whether a template can be saved
+pub fn check(template: &str) -> Result<(), Vec<HtmlError>> {
+    parse(template).map(|_| ())
+}
+
+// Everything a template can see: the school's name and the pupil's card
+pub fn context(card: &Card, school: &str) -> Value {
+    let reports: Vec<Value> = card.reports.iter()
+        .map(|(subject, content)| serde_json::json!({ "subject": subject.replace('_', " "), "content": content }))
+        .collect();
+    serde_json::json!({
+        "school": school,
+        "term": crate::template::capitalise(&card.term),
+        "year": card.year,
+        "pupil": { "name": card.name, "class": card.class, "birthdate": card.birthdate },
+        "tutors": card.tutors,
+        "tutor_comment": card.tutor_comment,
+        "reports": reports,
+    })
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(values) => !values.is_empty(),
+        Value::Object(values) => !values.is_empty(),
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null | Value::Object(_) => String::new(),
+        Value::String(text) => text.clone(),
+        // lists of reports print nothing rather than a comma for each
+        Value::Array(values) => values.iter().map(text).filter(|text| !text.is_empty()).collect::<Vec<String>>().join(", "),
+        value => value.to_string(),
+    }
+}
+
+// Writes nodes out, looking variables up in the loops' scopes then the context
+struct Renderer<'a> {
+    context: &'a Value,
+    scope: Vec<(String, Value)>,
+    output: String,
+    steps: usize,
+}
+
+impl Renderer<'_> {
+    fn lookup(&self, path: &Path) -> Value {
+        let mut parts = path.parts.iter();
+        let root = match parts.next() {
+            Some(root) => root,
+            None => return Value::Null,
+        };
+        let mut value = match self.scope.iter().rev().find(|(name, _)| name == root) {
+            Some((_, value)) => value,
+            None => self.context.get(root).unwrap_or(&Value::Null),
+        };
+        for part in parts {
+            value = value.get(part).unwrap_or(&Value::Null);
+        }
+        value.clone()
+    }
+
+    // the expression's value as escaped HTML
+    fn value(&self, expr: &Expr) -> String {
+        let mut value = self.lookup(&expr.path);
+        for (filter, argument) in &expr.filters {
+            value = match filter.as_str() {
+                "upper" => Value::String(text(&value).to_uppercase()),
+                "lower" => Value::String(text(&value).to_lowercase()),
+                "capitalize" => Value::String(crate::template::capitalise(&text(&value))),
+                "length" => Value::from(match &value {
+                    Value::Array(values) => values.len(),
+                    value => text(value).chars().count(),
+                }),
+                "default" if !truthy(&value) => Value::String(argument.clone().unwrap_or_default()),
+                "join" => Value::String(match &value {
+                    Value::Array(values) => values.iter().map(text).collect::<Vec<String>>().join(argument.as_deref().unwrap_or(", ")),
+                    value => text(value),
+                }),
+                // paragraphs at blank lines and line breaks within them, the only filter which makes HTML
+                "paragraphs" => return text(&value).split("\n\n").filter(|paragraph| !paragraph.trim().is_empty())
+                    .map(|paragraph| format!("<p>{}</p>", paragraph.trim().lines().map(escape).collect::<Vec<String>>().join("<br>")))
+                    .collect(),
+                _ => value,
+            };
+        }
+        escape(&text(&value))
+    }
+
+    fn test(&self, test: &Test) -> bool {
+        match test {
+            // a filtered value is true when there's something to print
+            Test::Truthy(expr) if expr.filters.is_empty() => truthy(&self.lookup(&expr.path)),
+            Test::Truthy(expr) => !self.value(expr).is_empty(),
+            Test::Not(expr) => !self.test(&Test::Truthy(expr.clone())),
+            Test::Equals(expr, value) => self.value(expr) == escape(value),
+            Test::NotEquals(expr, value) => self.value(expr) != escape(value),
+        }
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) -> Result<(), HtmlError> {
+        for node in nodes {
+            self.steps += 1;
+            if self.steps > MAX_STEPS { return Err(HtmlError::TooSlow) }
+            match node {
+                Node::Text(text) => self.output.push_str(text),
+                Node::Output(expr) => {
+                    let value = self.value(expr);
+                    self.output.push_str(&value);
+                },
+                Node::If { test, then, otherwise } => {
+                    if self.test(test) { self.nodes(then)? } else { self.nodes(otherwise)? }
+                },
+                Node::For { name, list, body } => {
+                    let values = match self.lookup(list) {
+                        Value::Array(values) => values,
+                        _ => vec![],
+                    };
+                    let count = values.len();
+                    for (i, value) in values.into_iter().enumerate() {
+                        let mut info = Map::new();
+                        info.insert("index".to_string(), Value::from(i + 1));
+                        info.insert("first".to_string(), Value::from(i == 0));
+                        info.insert("last".to_string(), Value::from(i + 1 == count));
+                        self.scope.extend([(name.clone(), value), ("loop".to_string(), Value::Object(info))]);
+                        let done = self.nodes(body);
+                        self.scope.truncate(self.scope.len() - 2);
+                        done?;
+                    }
+                },
+            }
+            if self.output.len() > MAX_OUTPUT { return Err(HtmlError::TooBig) }
+        }
+        Ok(())
+    }
+}
+
+// The template filled in from the context
+pub fn render(template: &str, context: &Value) -> Result<String, Vec<HtmlError>> {
+    let nodes = parse(template)?;
+    let mut renderer = Renderer { context, scope: vec![], output: String::new(), steps: 0 };
+    renderer.nodes(&nodes).map_err(|err| vec![err])?;
+    Ok(renderer.output)
+}
+
+// what's used until a school uploads its own
+pub const DEFAULT: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>{{ pupil.name }}: {{ term }} {{ year }}</title></head>
+<body>
+<h1>{{ school }}</h1>
+<h2>Report: {{ term }} {{ year }}</h2>
+<p>Name: {{ pupil.name }}<br>Class: {{ pupil.class }}{% if tutors %}<br>Tutor: {{ tutors | join(", ") }}{% endif %}</p>
+{% for report in reports %}<section>
+<h3>{{ report.subject }}</h3>
+{{ report.content | paragraphs }}
+</section>
+{% endfor %}{% if not reports %}<p>No reports have been written for this term.</p>{% endif %}
+{% if tutor_comment %}<section>
+<h3>Tutor's comment</h3>
+{{ tutor_comment | paragraphs }}
+</section>{% endif %}
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::{ check, context, render, HtmlError, DEFAULT };
+    use crate::card::Card;
+
+    fn card() -> Card {
+        Card {
+            pupil_id: 7,
+            name: "Sam O'Neill".to_string(),
+            class: "9A".to_string(),
+            birthdate: "2011-03-04".to_string(),
+            term: "autumn".to_string(),
+            year: Some(2024),
+            tutors: vec!["Ms Patel".to_string(), "Mr Cole".to_string()],
+            tutor_comment: None,
+            reports: vec![
+                ("Design_Technology".to_string(), "Sam works <carefully>.\n\nHe should ask for help.".to_string()),
+                ("Maths".to_string(), "Good.".to_string()),
+            ],
+        }
+    }
+
+    #[test]
+    fn cards_are_filled_in_and_escaped() {
+        let template = "{# heading #}<h1>{{ pupil.name | upper }}</h1>{% for report in reports %}\
+            <h2>{{ loop.index }}. {{ report.subject }}</h2>{{ report.content | paragraphs }}{% if not loop.last %}<hr>{% endif %}{% endfor %}\
+            <p>{{ tutor_comment | default(\"None this term\") }}; {{ tutors | join(\" & \") }}; {{ reports | length }}</p>\
+            {% if term == \"Autumn\" %}{{ year }}{% else %}not autumn{% endif %}";
+        let context = context(&card(), "Hill School");
+        assert_eq!(render(template, &context).unwrap(), "<h1>SAM O&#39;NEILL</h1>\
+            <h2>1. Design Technology</h2><p>Sam works &lt;carefully&gt;.</p><p>He should ask for help.</p><hr>\
+            <h2>2. Maths</h2><p>Good.</p>\
+            <p>None this term; Ms Patel &amp; Mr Cole; 2</p>2024");
+        let page = render(DEFAULT, &context).unwrap();
+        assert!(page.contains("<h1>Hill School</h1>") && page.contains("Tutor: Ms Patel, Mr Cole") && !page.contains("Tutor's comment"));
+        assert!(!page.contains("No reports") && render(DEFAULT, &super::context(&Card { reports: vec![], ..card() }, "")).unwrap().contains("No reports"));
+    }
+
+    #[test]
+    fn mistakes_are_found_on_upload() {
+        assert_eq!(check(DEFAULT), Ok(()));
+        assert_eq!(check("<p>{{ pupil.name </p>"), Err(vec![HtmlError::Unclosed { at: 3 }]));
+        assert_eq!(check("ab{% if pupil %}"), Err(vec![HtmlError::Unclosed { at: 2 }]));
+        assert_eq!(check("{{ report.content }} {{ pupil.name | shout }}"), Err(vec![
+            HtmlError::UnknownVariable { at: 0, name: "report".to_string() },
+            HtmlError::UnknownFilter { at: 21, name: "shout".to_string() },
+        ]));
+        assert_eq!(check("{% for report in reports %}{% else %}{% endfor %}{% endif %}"), Err(vec![
+            HtmlError::Unexpected { at: 27, found: "else".to_string() },
+            HtmlError::Unexpected { at: 49, found: "endif".to_string() },
+        ]));
+        assert_eq!(check("{{ pupil.name | paragraphs | upper }}"), Err(vec![HtmlError::Unexpected { at: 0, found: "paragraphs".to_string() }]));
+        let deep = "{% if pupil %}".repeat(9) + &"{% endif %}".repeat(9);
+        assert!(check(&deep).unwrap_err().contains(&HtmlError::TooDeep { at: 8 * 14 }));
+        let loops = "{% for a in reports %}".repeat(3) + &"{% endfor %}".repeat(3);
+        assert!(check(&loops).unwrap_err().contains(&HtmlError::TooDeep { at: 2 * 22 }));
+        assert_eq!(check(&"a".repeat(50_001)), Err(vec![HtmlError::TooLong { max: 50_000 }]));
+    }
+
+    #[test]
+    fn templates_only_see_the_card() {
+        // nothing outside the context can be named, and only its lists can be looped over
+        assert!(check("{{ env.home }}").is_err() && check("{% for file in files %}{% endfor %}").is_err());
+        let context = context(&card(), "Hill School");
+        assert_eq!(render("{% for c in pupil.name %}x{% endfor %}{{ pupil.secret }}{{ reports }}", &context).unwrap(), "");
+        let loud = "{% for a in reports %}{% for b in tutors %}{{ reports }}{% endfor %}{% endfor %}";
+        assert_eq!(render(&loud.replace("{{ reports }}", &"{{ pupil.name }}".repeat(1_000)), &context).map(|page| page.len()), Ok(4 * 1_000 * 15));
+        // printing nothing still counts
+        let many = super::context(&Card { reports: vec![("Maths".to_string(), String::new()); 400], ..card() }, "");
+        let quiet = "{% for a in reports %}{% for b in reports %}{% if pupil.secret %}x{% endif %}{% endfor %}{% endfor %}";
+        assert_eq!(render(quiet, &many), Err(vec![HtmlError::TooSlow]));
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::{template::Pronouns, validate};
+
+// Pupils from the CSV a school's MIS exports. The file's columns are matched to ours by the mapping sent with it,
+// or by the headings MIS exports usually have, each row is checked, then matched against the pupils already here.
+// What a file would do is planned without touching anything, see plan, and sql::Pupils::import carries a plan out.
+
+// rows in one file, more than a large secondary school
+pub const MAX_ROWS: usize = 5000;
+// our fields and the headings each is found under when the mapping doesn't say, compared without case or spaces.
+// An MIS's own ID column is its admission number or UPN, not our id, so id is only read from a column the mapping names.
+const FIELDS: [(&str, &[&str]); 7] = [
+    ("id", &[]),
+    ("first_name", &["forename", "legalforename", "firstname", "givenname"]),
+    ("last_name", &["surname", "legalsurname", "lastname", "familyname"]),
+    ("birthdate", &["dob", "dateofbirth", "birthdate"]),
+    ("class", &["reggroup", "registrationgroup", "registrationform", "form", "tutorgroup"]),
+    ("preferred_name", &["preferredforename", "preferredname", "knownas"]),
+    ("pronouns", &["pronouns"]),
+];
+const REQUIRED: [&str; 3] = ["first_name", "last_name", "birthdate"];
+
+// How dates are written in the file. ISO dates, with the year first, are read whichever this is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DateOrder {
+    #[default]
+    DayMonthYear,
+    MonthDayYear,
+}
+
+// The file and how to read it. columns maps our fields to the file's headings, see FIELDS.
+#[derive(Debug, Deserialize)]
+pub struct Import {
+    pub csv: String,
+    #[serde(default)]
+    pub columns: HashMap<String, String>,
+    #[serde(default)]
+    pub date_order: DateOrder,
+    // commit the rows which can be, rather than nothing while any conflict
+    #[serde(default)]
+    pub skip_conflicts: bool,
+}
+
+// Something wrong with the whole file, lines count from 1 with the headings
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum ImportError {
+    Empty,
+    UnclosedQuote { line: usize },
+    TooManyRows { max: usize },
+    UnknownField { field: String },
+    // the mapping names a heading the file hasn't got
+    NoHeading { heading: String },
+    NoColumn { field: String },
+}
+
+// A pupil as the file has them, or as they are already when it's one of ours.
+// Fields which are None weren't in the file, and are left as they are.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct Record {
+    pub line: usize,
+    pub id: Option<usize>,
+    pub first_name: String,
+    pub last_name: String,
+    pub birthdate: String,
+    pub class: Option<String>,
+    pub preferred_name: Option<String>,
+    pub pronouns: Option<String>,
+}
+
+// A row which can't be imported as it is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "conflict", rename_all = "snake_case")]
+pub enum Conflict {
+    Missing { line: usize, field: &'static str },
+    Date { line: usize, value: String },
+    Pronouns { line: usize, value: String },
+    Id { line: usize, value: String },
+    NoClass { line: usize, class: String },
+    NoPupil { line: usize, id: usize },
+    // the pupil with the id has another name and birthdate, so the id is likely from somewhere else
+    Mismatch { line: usize, id: usize },
+    // the same pupil on an earlier line
+    Repeated { line: usize, first: usize },
+    // more than one pupil here with the name and birthdate
+    Ambiguous { line: usize, pupil_ids: Vec<usize> },
+    // pupils here with the name but another birthdate, who may be the same pupil with a mistake in one or the other
+    Similar { line: usize, pupil_ids: Vec<usize> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    pub field: &'static str,
+    pub from: String,
+    pub to: String,
+}
+
+// An existing pupil and what the file changes about them. record is them as they'll be.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Update {
+    pub record: Record,
+    pub changes: Vec<Change>,
+}
+
+// What importing a file does: creates have no id until they're committed
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Plan {
+    pub creates: Vec<Record>,
+    pub updates: Vec<Update>,
+    pub unchanged: usize,
+    pub conflicts: Vec<Conflict>,
+}
+
+// Rows of fields, quoted or not, with line breaks allowed in quotes and a byte order mark skipped. Blank lines are left out.
+pub fn csv(text: &str) -> Result<Vec<(usize, Vec<String>)>, ImportError> {
+    let mut rows = vec![];
+    let (mut row, mut field) = (vec![], String::new());
+    let (mut line, mut start, mut quoted) = (1, 1, false);
+    let mut chars = text.strip_prefix('\u{feff}').unwrap_or(text).chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            (true, '"') => quoted = false,
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => (),
+            (false, '\n' | '\r') => {
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|field: &String| !field.trim().is_empty()) { rows.push((start, std::mem::take(&mut row))) }
+                row.clear();
+                line += 1;
+                start = line;
+            },
+            (_, c) => {
+                if c == '\n' { line += 1 }
+                field.push(c);
+            },
+        }
+    }
+    if quoted { return Err(ImportError::UnclosedQuote { line: start }) }
+    row.push(field);
+    if row.iter().any(|field| !field.trim().is_empty()) { rows.push((start, row)) }
+    Ok(rows)
+}
+
+// A date in the file as YYYY-MM-DD, or None if it isn't one. Parts can be split by / - or . and days and months needn't have a leading 0.
+pub fn date(text: &str, order: DateOrder) -> Option<String> {
+    let parts: Vec<&str> = text.trim().split(['/', '-', '.']).collect();
+    let (year, month, day) = match (parts.as_slice(), order) {
+        ([year, month, day], _) if year.len() == 4 => (*year, *month, *day),
+        ([day, month, year], DateOrder::DayMonthYear) | ([month, day, year], DateOrder::MonthDayYear) if year.len() == 4 => (*year, *month, *day),
+        _ => return None,
+    };
+    let (month, day): (u8, u8) = match (month.parse(), day.parse()) {
+        (Ok(month), Ok(day)) => (month, day),
+        _ => return None,
+    };
+    let date = format!("{}-{:02}-{:02}", year, month, day);
+    validate::date(&date).then_some(date)
+}
+
+fn squash(heading: &str) -> String {
+    heading.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+// Which column of the file each of our fields is in
+fn columns(headings: &[String], mapping: &HashMap<String, String>) -> Result<HashMap<&'static str, usize>, ImportError> {
+    if let Some(field) = mapping.keys().find(|field| !FIELDS.iter().any(|(ours, _)| ours == field)) {
+        return Err(ImportError::UnknownField { field: field.clone() });
+    }
+    let mut columns = HashMap::new();
+    for (field, aliases) in FIELDS {
+        let column = match mapping.get(field) {
+            Some(heading) => Some(headings.iter().position(|found| found.trim() == heading.trim())
+                .ok_or(ImportError::NoHeading { heading: heading.clone() })?),
+            None if field == "id" => None,
+            None => headings.iter().position(|found| squash(found) == squash(field) || aliases.contains(&squash(found).as_str())),
+        };
+        match column {
+            Some(column) => { columns.insert(field, column); },
+            None if REQUIRED.contains(&field) => return Err(ImportError::NoColumn { field: field.to_string() }),
+            None => (),
+        }
+    }
+    Ok(columns)
+}
+
+// Each row as a record, or why it can't be one
+pub fn records(import: &Import) -> Result<Vec<Result<Record, Conflict>>, ImportError> {
+    let mut rows = csv(&import.csv)?.into_iter();
+    let (_, headings) = rows.next().ok_or(ImportError::Empty)?;
+    if rows.len() > MAX_ROWS { return Err(ImportError::TooManyRows { max: MAX_ROWS }) }
+    let columns = columns(&headings, &import.columns)?;
+
+    Ok(rows.map(|(line, row)| {
+        let field = |name: &str| columns.get(name).map(|column| row.get(*column).map_or("", |value| value.trim()));
+        for name in REQUIRED {
+            if field(name).is_none_or(str::is_empty) { return Err(Conflict::Missing { line, field: name }) }
+        }
+        let value = field("birthdate").unwrap_or_default();
+        let birthdate = date(value, import.date_order).ok_or(Conflict::Date { line, value: value.to_string() })?;
+        let id = match field("id").filter(|id| !id.is_empty()) {
+            Some(id) => Some(id.parse().map_err(|_| Conflict::Id { line, value: id.to_string() })?),
+            None => None,
+        };
+        let pronouns = match field("pronouns") {
+            Some("") => Some(String::new()),
+            Some(value) => Some(value.parse::<Pronouns>().map_err(|_| Conflict::Pronouns { line, value: value.to_string() })?.to_string()),
+            None => None,
+        };
+        Ok(Record {
+            line,
+            id,
+            first_name: field("first_name").unwrap_or_default().to_string(),
+            last_name: field("last_name").unwrap_or_default().to_string(),
+            birthdate,
+            class: field("class").map(str::to_string),
+            preferred_name: field("preferred_name").map(str::to_string),
+            pronouns,
+        })
+    }).collect())
+}
+
+fn key(record: &Record) -> (String, String) {
+    (record.first_name.to_lowercase(), record.last_name.to_lowercase())
+}
+
+// The record's fields over the existing pupil's, and the ones which differ
+fn merge(existing: &Record, record: &Record) -> Update {
+    let mut merged = Record { line: record.line, id: existing.id, ..record.clone() };
+    let mut changes = vec![];
+    let mut compare = |field: &'static str, from: &str, to: &str| if from != to {
+        changes.push(Change { field, from: from.to_string(), to: to.to_string() });
+    };
+    compare("first_name", &existing.first_name, &record.first_name);
+    compare("last_name", &existing.last_name, &record.last_name);
+    compare("birthdate", &existing.birthdate, &record.birthdate);
+    // an empty class leaves the pupil where they are, pupils are only moved out of a class into another
+    merged.class = record.class.clone().filter(|class| !class.is_empty()).or(existing.class.clone());
+    for (field, from, to) in [("class", &existing.class, &mut merged.class), ("preferred_name", &existing.preferred_name, &mut merged.preferred_name),
+        ("pronouns", &existing.pronouns, &mut merged.pronouns)] {
+        if to.is_none() { to.clone_from(from) }
+        compare(field, from.as_deref().unwrap_or_default(), to.as_deref().unwrap_or_default());
+    }
+    Update { record: merged, changes }
+}
+
+// What the records would do to the existing pupils, with the classes there are. Pupils are matched by the id
+// when the file has one, otherwise by name and birthdate ignoring case.
+pub fn plan(records: Vec<Result<Record, Conflict>>, existing: &[Record], classes: &[String]) -> Plan {
+    let mut plan = Plan::default();
+    let mut by_name: HashMap<(String, String), Vec<&Record>> = HashMap::new();
+    for pupil in existing {
+        by_name.entry(key(pupil)).or_default().push(pupil);
+    }
+    // the line each pupil, existing by id or new by name and birthdate, was first seen on
+    let mut seen: HashMap<(Option<usize>, String, String, String), usize> = HashMap::new();
+
+    for record in records {
+        let record = match record {
+            Ok(record) => record,
+            Err(conflict) => {
+                plan.conflicts.push(conflict);
+                continue
+            },
+        };
+        let line = record.line;
+        if let Some(class) = record.class.as_ref().filter(|class| !class.is_empty() && !classes.contains(class)) {
+            plan.conflicts.push(Conflict::NoClass { line, class: class.clone() });
+            continue
+        }
+
+        let named = by_name.get(&key(&record)).map_or(&[][..], Vec::as_slice);
+        let matched: Vec<&Record> = match record.id {
+            Some(id) => existing.iter().filter(|pupil| pupil.id == Some(id)).collect(),
+            None => named.iter().copied().filter(|pupil| pupil.birthdate == record.birthdate).collect(),
+        };
+        let (first_name, last_name) = key(&record);
+        let identity = match matched.as_slice() {
+            [pupil] => (pupil.id, String::new(), String::new(), String::new()),
+            _ => (None, first_name, last_name, record.birthdate.clone()),
+        };
+        if let Some(first) = seen.get(&identity) {
+            plan.conflicts.push(Conflict::Repeated { line, first: *first });
+            continue
+        }
+        seen.insert(identity, line);
+
+        match (matched.as_slice(), record.id) {
+            ([pupil], Some(id)) if key(pupil) != key(&record) && pupil.birthdate != record.birthdate => {
+                plan.conflicts.push(Conflict::Mismatch { line, id });
+            },
+            ([pupil], _) => {
+                let update = merge(pupil, &record);
+                if update.changes.is_empty() { plan.unchanged += 1 } else { plan.updates.push(update) }
+            },
+            ([], Some(id)) => plan.conflicts.push(Conflict::NoPupil { line, id }),
+            ([], None) if !named.is_empty() => {
+                plan.conflicts.push(Conflict::Similar { line, pupil_ids: named.iter().filter_map(|pupil| pupil.id).collect() });
+            },
+            ([], None) => plan.creates.push(record),
+            (pupils, _) => plan.conflicts.push(Conflict::Ambiguous { line, pupil_ids: pupils.iter().filter_map(|pupil| pupil.id).collect() }),
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::{ csv, date, plan, records, Conflict, DateOrder, Import, ImportError, Record };
+
+    fn import(csv: &str) -> Import {
+        Import { csv: csv.to_string(), columns: HashMap::new(), date_order: DateOrder::DayMonthYear, skip_conflicts: false }
+    }
+
+    #[test]
+    fn csv_fields() {
+        let text = "\u{feff}Surname,Forename\r\n\"O'Neill, Jr\",\"Sam \"\"Sammy\"\"\nSecond line\"\r\n\r\nPatel,Priya";
+        assert_eq!(csv(text), Ok(vec![
+            (1, vec!["Surname".to_string(), "Forename".to_string()]),
+            (2, vec!["O'Neill, Jr".to_string(), "Sam \"Sammy\"\nSecond line".to_string()]),
+            (5, vec!["Patel".to_string(), "Priya".to_string()]),
+        ]));
+        assert_eq!(csv("a,b\n\"unfinished,c"), Err(ImportError::UnclosedQuote { line: 2 }));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(date("4/3/2011", DateOrder::DayMonthYear), Some("2011-03-04".to_string()));
+        assert_eq!(date("4/3/2011", DateOrder::MonthDayYear), Some("2011-04-03".to_string()));
+        assert_eq!(date("2011-03-04", DateOrder::MonthDayYear), Some("2011-03-04".to_string()));
+        assert_eq!(date("29.02.2012", DateOrder::DayMonthYear), Some("2012-02-29".to_string()));
+        assert_eq!(date("29/02/2011", DateOrder::DayMonthYear), None);
+        assert_eq!(date("4/3/11", DateOrder::DayMonthYear), None);
+    }
+
+    #[test]
+    fn columns_are_found_by_mapping_or_usual_headings() {
+        let file = "Legal Surname,Legal Forename,DOB,Reg Group,Pronouns\nO'Neill,Sam,04/03/2011,9A,He/Him\nJones,,01/01/2011,9A,\nLee,Jo,31/02/2011,9A,\nKim,Al,01/01/2011,9A,zz";
+        let rows = records(&import(file)).unwrap();
+        assert_eq!(rows[0].as_ref().unwrap().birthdate, "2011-03-04");
+        assert_eq!(rows[0].as_ref().unwrap().pronouns.as_deref(), Some("he/him"));
+        assert_eq!(rows[1], Err(Conflict::Missing { line: 3, field: "first_name" }));
+        assert_eq!(rows[2], Err(Conflict::Date { line: 4, value: "31/02/2011".to_string() }));
+        assert_eq!(rows[3], Err(Conflict::Pronouns { line: 5, value: "zz".to_string() }));
+
+        let mut mapped = import("Family,Given,Born\nO'Neill,Sam,2011-03-04");
+        mapped.columns = HashMap::from([("last_name".to_string(), "Family".to_string()), ("first_name".to_string(), "Given".to_string())]);
+        assert_eq!(records(&mapped).unwrap_err(), ImportError::NoColumn { field: "birthdate".to_string() });
+        mapped.columns.insert("birthdate".to_string(), "Born".to_string());
+        assert_eq!(records(&mapped).unwrap()[0].as_ref().unwrap().first_name, "Sam");
+        // an MIS's ID column is only ours when the mapping says so
+        let ids = import("Pupil ID,Surname,Forename,DOB\n4471,O'Neill,Sam,04/03/2011");
+        assert_eq!(records(&ids).unwrap()[0].as_ref().unwrap().id, None);
+        mapped.columns.insert("id".to_string(), "Born".to_string());
+        assert_eq!(records(&mapped).unwrap()[0], Err(Conflict::Id { line: 2, value: "2011-03-04".to_string() }));
+        mapped.columns.remove("id");
+        mapped.columns.insert("house".to_string(), "House".to_string());
+        assert_eq!(records(&mapped).unwrap_err(), ImportError::UnknownField { field: "house".to_string() });
+    }
+
+    #[test]
+    fn plans_creates_updates_and_conflicts() {
+        let pupil = |id, first: &str, birthdate: &str, class: &str| Record {
+            id: Some(id), first_name: first.to_string(), last_name: "Jones".to_string(), birthdate: birthdate.to_string(),
+            class: Some(class.to_string()), ..Record::default()
+        };
+        let existing = vec![pupil(1, "Sam", "2011-03-04", "9A"), pupil(2, "Alex", "2011-05-06", "9A"), pupil(3, "Jo", "2011-01-01", "9A"), pupil(4, "Jo", "2011-01-01", "9B")];
+        let file = "First name,Last name,Date of birth,Class\n\
+            sam,JONES,04/03/2011,9B\n\
+            Alex,Jones,06/05/2011,9A\n\
+            Alex,Jones,07/05/2011,9A\n\
+            Jo,Jones,01/01/2011,9A\n\
+            Priya,Patel,01/09/2011,9A\n\
+            Priya,Patel,01/09/2011,9A\n\
+            Kim,Lee,01/09/2011,10Z";
+        let plan = plan(records(&import(file)).unwrap(), &existing, &["9A".to_string(), "9B".to_string()]);
+
+        assert_eq!(plan.updates.len(), 1);
+        assert_eq!(plan.updates[0].record.id, Some(1));
+        assert_eq!(plan.updates[0].changes.iter().map(|change| change.field).collect::<Vec<_>>(), vec!["first_name", "last_name", "class"]);
+        assert_eq!(plan.unchanged, 1);
+        assert_eq!(plan.creates.iter().map(|record| record.line).collect::<Vec<_>>(), vec![6]);
+        assert_eq!(plan.conflicts, vec![
+            Conflict::Similar { line: 4, pupil_ids: vec![2] },
+            Conflict::Ambiguous { line: 5, pupil_ids: vec![3, 4] },
+            Conflict::Repeated { line: 7, first: 6 },
+            Conflict::NoClass { line: 8, class: "10Z".to_string() },
+        ]);
+
+        let mut by_id = import("Id,First name,Last name,Date of birth\n1,Sam,Jones,05/03/2011\n2,Kim,Lee,01/09/2011");
+        by_id.columns.insert("id".to_string(), "Id".to_string());
+        let plan = super::plan(records(&by_id).unwrap(), &existing, &["9A".to_string()]);
+        assert_eq!(plan.updates.iter().map(|update| update.record.id).collect::<Vec<_>>(), vec![Some(1)]);
+        assert_eq!(plan.conflicts, vec![Conflict::Mismatch { line: 3, id: 2 }]);
+    }
+}
@@ -1,17 +1,38 @@
+pub mod access;
+pub mod backup;
+pub mod card;
+pub mod export;
+pub mod html;
+pub mod import;
+pub mod lint;
+pub mod page;
 pub mod parse_connection;
+pub mod pdf;
+pub mod search;
+pub mod similar;
+pub mod spell;
+pub mod stats;
 pub mod sql;
+pub mod template;
+pub mod validate;
+pub mod zip;
 
 use serde::{Deserialize, Serialize};
 use tokio::{
     net::TcpStream,
-    io::AsyncWriteExt,
+    io::{self, AsyncWriteExt},
+};
+use mysql_async::prelude::Queryable;
+use std::{
+    collections::HashMap,
+    str::Split,
 };
-use std::str::Split;
 
 #[derive(Debug)]
 pub struct HttpRequest {
     method: String,
     uri: String,
+    #[allow(dead_code)]
     version: String,
     headers: Vec<String>,
     body: String,
@@ -23,15 +44,65 @@ pub struct HttpResponse {
     status: String,
     version: String,
     headers: Vec<String>,
-    body: String,
+    body: Vec<u8>,
+    // written after the head in place of the body
+    #[serde(skip)]
+    spreadsheet: Option<Spreadsheet>,
+}
+
+// A download written as it's read from the database rather than made first, see Spreadsheet::write
+#[derive(Debug)]
+pub struct Spreadsheet {
+    name: String,
+    format: export::Format,
+    export: sql::Export,
+}
+
+// A download rather than json: a pdf or zip, with the name it's saved under, or a page shown as it is when there's no name
+#[derive(Debug)]
+pub struct File {
+    name: Option<String>,
+    kind: &'static str,
+    bytes: Vec<u8>,
 }
 
 #[derive(Debug, Serialize)]
 pub enum Body {
+    Report(sql::Report),
     Reports(sql::Reports),
     Class(sql::Class),
     Classes(sql::Classes),
     Pupils(sql::Pupils),
+    StatusChange(sql::StatusChange),
+    Home(sql::Home),
+    Assignments(sql::Assignments),
+    Assignment(sql::Assignment),
+    Pupil(sql::Pupil),
+    Subject(sql::Subject),
+    Names(Vec<String>),
+    Deleted(String),
+    Transfer(sql::Transfer),
+    History(Vec<sql::Move>),
+    Matches(sql::Matches),
+    Comment(sql::Comment),
+    Comments(sql::Comments),
+    Text(String),
+    Issues(Vec<lint::Issue>),
+    Misspellings(Vec<spell::Misspelling>),
+    Statistics(sql::Statistics),
+    Duplicates(sql::Duplicates),
+    Layout(card::Layout),
+    TutorComment(sql::TutorComment),
+    Import(import::Plan),
+    Restored(backup::Summary),
+    // sent as it is, see HttpResponse::file
+    #[serde(skip)]
+    File(File),
+    // cards, with those left out as they couldn't be printed
+    #[serde(skip)]
+    Cards(File, Vec<card::Unprintable>),
+    #[serde(skip)]
+    Spreadsheet(Spreadsheet),
 }
 
 #[derive(Debug, Serialize)]
@@ -50,6 +121,117 @@ pub enum BadRequest {
     Forbidden,
     Login,
     Body,
+    Status,
+    Locked(sql::Status),
+    Transition(sql::Status, sql::Status),
+    Invalid(Vec<validate::FieldError>),
+    Conflict(Box<sql::Report>),
+    InClass(Vec<usize>),
+    Name,
+    Date,
+    NotInSet(String),
+    InSet(String),
+    Cursor,
+    Text(Vec<validate::Invalid>),
+    Template(Vec<template::TemplateError>),
+    Pronouns,
+    Html(Vec<html::HtmlError>),
+    Import(import::ImportError),
+    // an import with conflicts, committed only when it says to skip them
+    Plan(Box<import::Plan>),
+    Backup(backup::BackupError),
+    // text on cards which a pdf can't print
+    Unprintable(Vec<card::Unprintable>),
+    // a request which couldn't be read, so never got routed: not UTF-8, cut short, too large
+    Unreadable,
+}
+
+impl BadRequest {
+    fn status(&self) -> &'static str {
+        match self {
+            BadRequest::NotFound => "404 Not Found",
+            BadRequest::Forbidden => "403 Forbidden",
+            BadRequest::Login => "401 Unauthorized",
+            BadRequest::DB => "500 Internal Server Error",
+            BadRequest::Locked(_) | BadRequest::Transition(..) | BadRequest::Conflict(_)
+                | BadRequest::InClass(_) | BadRequest::NotInSet(_) | BadRequest::InSet(_) | BadRequest::Plan(_)
+                | BadRequest::Backup(backup::BackupError::NotEmpty) => "409 Conflict",
+            BadRequest::Invalid(_) | BadRequest::Text(_) | BadRequest::Template(_)
+                | BadRequest::Html(_) | BadRequest::Import(_) | BadRequest::Backup(_)
+                | BadRequest::Unprintable(_) => "422 Unprocessable Entity",
+            _ => "400 Bad Request",
+        }
+    }
+}
+
+impl From<sql::ReportError> for BadRequest {
+    fn from(err: sql::ReportError) -> BadRequest {
+        match err {
+            sql::ReportError::DB(_) => BadRequest::DB,
+            sql::ReportError::Locked(status) => BadRequest::Locked(status),
+            sql::ReportError::Transition(from, to) => BadRequest::Transition(from, to),
+            sql::ReportError::Conflict(current) => BadRequest::Conflict(current),
+            sql::ReportError::Missing => BadRequest::NotFound,
+        }
+    }
+}
+
+impl From<sql::RestoreError> for BadRequest {
+    fn from(err: sql::RestoreError) -> BadRequest {
+        match err {
+            sql::RestoreError::DB(_) => BadRequest::DB,
+            sql::RestoreError::Backup(err) => BadRequest::Backup(err),
+        }
+    }
+}
+
+impl From<sql::AdminError> for BadRequest {
+    fn from(err: sql::AdminError) -> BadRequest {
+        match err {
+            sql::AdminError::DB(_) => BadRequest::DB,
+            sql::AdminError::InClass(ids) => BadRequest::InClass(ids),
+            sql::AdminError::NoClass(_) | sql::AdminError::NoPupil(_) => BadRequest::NotFound,
+            sql::AdminError::NotInSet(subject) => BadRequest::NotInSet(subject),
+            sql::AdminError::InSet(subject) => BadRequest::InSet(subject),
+            sql::AdminError::Conflicts(plan) => BadRequest::Plan(plan),
+        }
+    }
+}
+
+// a query string value with its %XX escapes and '+' spaces decoded, None if they don't make utf-8
+fn decode(value: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut rest = value.bytes();
+    while let Some(byte) = rest.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [rest.next()?, rest.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            },
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// The fields a template gets from a pupil, which win over values sent with the request.
+// first_name is the name the pupil goes by.
+fn pupil_values(pupil: &sql::Pupil, mut values: HashMap<String, String>) -> HashMap<String, String> {
+    values.insert("first_name".to_string(), pupil.known_as().to_string());
+    values.insert("last_name".to_string(), pupil.last_name().to_string());
+    values.insert("name".to_string(), format!("{} {}", pupil.known_as(), pupil.last_name()));
+    values
+}
+
+// words added to the spell checker, which takes letters, apostrophes and hyphens
+fn dictionary_word(word: &str) -> bool {
+    !word.is_empty() && word.chars().count() <= 50 && word.chars().all(|c| c.is_alphabetic() || c == '\'' || c == '-')
+}
+
+// class and subject names become table names
+fn table_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 30 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
 
 impl HttpRequest {
@@ -58,14 +240,14 @@ impl HttpRequest {
         HttpRequest { method , uri, version, headers, body, stream }
     }
 
+    #[allow(dead_code)]
     fn cookie(&self) -> Option<()> {
-        let cookie = for header in &self.headers {
+        for header in &self.headers {
             let mut header = header.split(' ');
             if header.next() == Some("Cookie:") { 
                 return Some(())
             } 
-        };
-        println!("{:?}", cookie);
+        }
         None
         /*
            TODO:
@@ -73,96 +255,1085 @@ impl HttpRequest {
         */
     }
 
+    fn path(&self) -> &str {
+        self.uri.split('?').next().unwrap_or("")
+    }
+
+    // value for key in the uri's query string: /<school>/<teacher>/<params>?key=value&...
+    fn query(&self, key: &str) -> Option<&str> {
+        let (_, query) = self.uri.split_once('?')?;
+        query.split('&').find_map(|pair| match pair.split_once('=') {
+            Some((k, value)) if k == key => Some(value),
+            _ => None,
+        })
+    }
+
+    // ?after=<cursor>&limit=<n> on the endpoints that list pupils or reports
+    fn page(&self) -> Result<page::Page, BadRequest> {
+        let limit = match self.query("limit").map(str::parse) {
+            Some(Ok(limit)) => Some(limit),
+            Some(Err(_)) => return Err(BadRequest::Params),
+            None => None,
+        };
+        page::Page::new(self.query("after"), limit).ok_or(BadRequest::Cursor)
+    }
+
+    // a page of a list sorted by pupil id, refusing a cursor from any other list
+    fn id_page(&self) -> Result<page::Page, BadRequest> {
+        let page = self.page()?;
+        if page.after_id().is_none() { return Err(BadRequest::Cursor) }
+        Ok(page)
+    }
+
+    // ?name=<words>&fuzzy=true&class=<class>&born=<year> when looking for pupils
+    fn pupil_search(&self) -> Result<sql::Search, BadRequest> {
+        let name = match self.query("name").map(decode) {
+            Some(Some(name)) => Some(name).filter(|name| !name.trim().is_empty()),
+            Some(None) => return Err(BadRequest::Params),
+            None => None,
+        };
+        let born = match self.query("born").map(str::parse) {
+            Some(Ok(born)) => Some(born),
+            Some(Err(_)) => return Err(BadRequest::Params),
+            None => None,
+        };
+        let class = self.query("class").filter(|class| !class.is_empty());
+        if class.is_some_and(|class| !table_name(class)) { return Err(BadRequest::Name) }
+        Ok(sql::Search::new(name, self.query("fuzzy") == Some("true"), class.map(str::to_string), born))
+    }
+
+    fn json<'a, T: Deserialize<'a>>(&'a self) -> Result<T, BadRequest> {
+        serde_json::from_str(&self.body).map_err(|_| BadRequest::Body)
+    }
+
+    async fn role(&self, teacher: &str, conn: &mut mysql_async::Conn) -> Result<sql::Role, BadRequest> {
+        match sql::Teacher::new(teacher.to_string()).role(conn).await {
+            Ok(Some(role)) => Ok(role),
+            Ok(None) => Err(BadRequest::Forbidden),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
+    async fn admin(&self, teacher: &str, conn: &mut mysql_async::Conn) -> Result<(), BadRequest> {
+        if self.role(teacher, conn).await? < sql::Role::Admin { return Err(BadRequest::Forbidden) }
+        Ok(())
+    }
+
+    // heads of year and admins see every class, everyone else needs an assignment covering it
+    async fn access(&self, teacher: &str, role: sql::Role, class: &str, subject: Option<&str>, conn: &mut mysql_async::Conn) -> Result<(), BadRequest> {
+        if role >= sql::Role::HeadOfYear { return Ok(()) }
+        match sql::Teacher::new(teacher.to_string()).access(class, subject, conn).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(BadRequest::Forbidden),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
+    // every subject's reports for a class are for its form tutors, heads of year and admins, not anyone teaching it one subject
+    async fn tutor(&self, teacher: &str, role: sql::Role, class: &str, conn: &mut mysql_async::Conn) -> Result<(), BadRequest> {
+        if role >= sql::Role::HeadOfYear { return Ok(()) }
+        match sql::Teacher::new(teacher.to_string()).tutors(class, conn).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(BadRequest::Forbidden),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
+    // reports are written by the subject's teacher for the class, or an admin
+    async fn writes(&self, teacher: &str, role: sql::Role, class: &str, subject: &str, conn: &mut mysql_async::Conn) -> Result<(), BadRequest> {
+        if role >= sql::Role::Admin { return Ok(()) }
+        match sql::Teacher::new(teacher.to_string()).teaches(class, subject, conn).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(BadRequest::Forbidden),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
+    async fn get_pupil(&self, id: usize, conn: &mut mysql_async::Conn) -> Result<sql::Pupil, BadRequest> {
+        match sql::Pupil::get(id, conn).await {
+            Ok(Some(pupil)) => Ok(pupil),
+            Ok(None) => Err(BadRequest::NotFound),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
+    // subjects and terms end up in queries as table and column names
+    async fn subject_term(&self, subject: &str, term: &str, conn: &mut mysql_async::Conn) -> Result<(), BadRequest> {
+        if !sql::TERMS.contains(&term) { return Err(BadRequest::NoTerm) }
+        self.existing_subject(subject, conn).await
+    }
+
+    async fn existing_subject(&self, subject: &str, conn: &mut mysql_async::Conn) -> Result<(), BadRequest> {
+        match sql::Subject::new(subject.to_string()).exists(conn).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(BadRequest::TermSubject),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
     pub async fn class(&self, _school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+
         let class = if let Some(id) = params.next() {
             sql::Class::new(id.to_string())
-        } else { return Ok(Body::Classes(sql::Classes::new(teacher.to_string())))};
-
-        let conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
-            else { return Err(BadRequest::DB) };
+        } else { 
+            return match sql::Classes::new(teacher.to_string(), role, &mut conn).await {
+                Ok(classes) => Ok(Body::Classes(classes)),
+                Err(_) => Err(BadRequest::DB),
+            }
+        };
 
         if let Some(reports) = params.next() {
             match reports {
                 "reports" => {
                     let subject = if let Some(subject) = params.next() { subject }
                         else { return Err(BadRequest::TermSubject) };
+                    self.access(teacher, role, class.name(), Some(subject), &mut conn).await?;
                     let terms: Vec<&str> = params.collect();
-                    match class.reports(conn, subject, terms).await {
+                    if !terms.iter().all(|term| sql::TERMS.contains(term)) { return Err(BadRequest::NoTerm) }
+                    let status = match self.query("status").map(str::parse) {
+                        Some(Ok(status)) => Some(status),
+                        Some(Err(_)) => return Err(BadRequest::Status),
+                        None => None,
+                    };
+                    let page = self.id_page()?;
+                    match class.reports(&mut conn, subject, terms, status, &page).await {
                         Ok(reports) => Ok(Body::Reports(reports)),
                         Err(_) => Err(BadRequest::Reports)
                     }
                 },
-                _ => return Err(BadRequest::Params)
+                _ => Err(BadRequest::Params)
             }
         } else { 
-            let pupils = if let Ok(pupils) = class.pupils(conn).await { pupils }
+            self.access(teacher, role, class.name(), None, &mut conn).await?;
+            let page = self.page()?;
+            let pupils = if let Ok(pupils) = class.pupils(&page, conn).await { pupils }
                 else { return Err(BadRequest::Pupils) };
-            return Ok(Body::Pupils(pupils)) 
+            Ok(Body::Pupils(pupils)) 
         }
     }
 
-    pub async fn home(&self, school: &str, teacher: &str) -> Result<Body, BadRequest> {
-        todo!()
+    // a pupil's reports across every subject, for form tutors: pupil+<id>+reports+<term>+<term>?year=<year>
+    pub async fn pupil(&self, _school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let id = if let Some(Ok(id)) = params.next().map(str::parse) { id }
+            else { return Err(BadRequest::Params) };
+        if params.next() != Some("reports") { return Err(BadRequest::Params) }
+
+        let terms: Vec<&str> = params.collect();
+        if !terms.iter().all(|term| sql::TERMS.contains(term)) { return Err(BadRequest::NoTerm) }
+        let year = match self.query("year").map(str::parse) {
+            Some(Ok(year)) => Some(year),
+            Some(Err(_)) => return Err(BadRequest::Params),
+            None => None,
+        };
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        let pupil = self.get_pupil(id, &mut conn).await?;
+        self.tutor(teacher, role, pupil.class(), &mut conn).await?;
+
+        match pupil.reports(conn, terms, year).await {
+            Ok(reports) => Ok(Body::Reports(reports)),
+            Err(_) => Err(BadRequest::Reports),
+        }
+    }
+
+    // reports containing every word or "phrase" of q, in whatever the teacher can read:
+    // search?q=<words>&subject=<subject>&term=<term>&year=<year>&class=<class>&author=<teacher>&after=<cursor>&limit=<n>
+    pub async fn search(&self, _school: &str, teacher: &str) -> Result<Body, BadRequest> {
+        let terms = match self.query("q").map(decode) {
+            Some(Some(query)) => search::terms(&query),
+            _ => return Err(BadRequest::Params),
+        };
+        if terms.is_empty() || terms.len() > search::MAX_TERMS { return Err(BadRequest::Params) }
+        let term = self.query("term");
+        if term.is_some_and(|term| !sql::TERMS.contains(&term)) { return Err(BadRequest::NoTerm) }
+        let year = match self.query("year").map(str::parse) {
+            Some(Ok(year)) => Some(year),
+            Some(Err(_)) => return Err(BadRequest::Params),
+            None => None,
+        };
+        let class = self.query("class");
+        if class.is_some_and(|class| !table_name(class)) { return Err(BadRequest::Name) }
+        let author = match self.query("author").map(decode) {
+            Some(Some(author)) => Some(author),
+            Some(None) => return Err(BadRequest::Params),
+            None => None,
+        };
+        let page = self.page()?;
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        let subject = self.query("subject");
+        if let Some(subject) = subject { self.existing_subject(subject, &mut conn).await? }
+
+        let reader = if role >= sql::Role::HeadOfYear { None } else { Some(teacher) };
+        let search = sql::ReportSearch::new(terms)
+            .subject(subject.map(str::to_string))
+            .term(term.map(str::to_string))
+            .year(year)
+            .class(class.map(str::to_string))
+            .author(author);
+        search.find(reader, &page, &mut conn).await.map(Body::Matches).map_err(|_| BadRequest::DB)
+    }
+
+    // Length and readability of a term's reports, each and summed up, in whatever the teacher can read:
+    // stats+teacher+<name>+<term>, stats+class+<class>+<term>, stats+subject+<subject>+<term>
+    pub async fn stats(&self, _school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let (by, name, term) = match (params.next(), params.next().map(decode), params.next(), params.next()) {
+            (Some(by), Some(Some(name)), Some(term), None) => (by, name, term),
+            _ => return Err(BadRequest::Params),
+        };
+        if !sql::TERMS.contains(&term) { return Err(BadRequest::NoTerm) }
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        let query = sql::StatsQuery::new(term.to_string());
+        let query = match by {
+            "teacher" => query.author(Some(name)),
+            "class" => {
+                self.existing_class(&name, &mut conn).await?;
+                query.class(Some(name))
+            },
+            "subject" => {
+                self.existing_subject(&name, &mut conn).await?;
+                query.subject(Some(name))
+            },
+            _ => return Err(BadRequest::Params),
+        };
+
+        let reader = if role >= sql::Role::HeadOfYear { None } else { Some(teacher) };
+        query.find(reader, &mut conn).await.map(Body::Statistics).map_err(|_| BadRequest::DB)
+    }
+
+    // Near-duplicate reports for heads of year to look over before they're published:
+    // GET duplicates+<term>?class=<class> <- the pairs last found, POST duplicates+<term>?threshold=<0.5 to 1> <- compares the term's reports again,
+    // PUT duplicates+<term>+<subject>+<pupil_id>+<pupil_id> <- marks a pair reviewed
+    pub async fn duplicates(&self, _school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let term = match params.next() {
+            Some(term) if sql::TERMS.contains(&term) => term,
+            _ => return Err(BadRequest::NoTerm),
+        };
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        if self.role(teacher, &mut conn).await? < sql::Role::HeadOfYear { return Err(BadRequest::Forbidden) }
+
+        match (self.method.as_str(), params.next(), params.next(), params.next(), params.next()) {
+            ("GET", None, ..) => {
+                let class = self.query("class").filter(|class| !class.is_empty());
+                if class.is_some_and(|class| !table_name(class)) { return Err(BadRequest::Name) }
+                sql::Duplicates::get(term, class, &mut conn).await.map(Body::Duplicates).map_err(|_| BadRequest::DB)
+            },
+            ("POST", None, ..) => {
+                let threshold = match self.query("threshold").map(str::parse::<f64>) {
+                    Some(Ok(threshold)) if (similar::MIN_THRESHOLD..=1.0).contains(&threshold) => threshold,
+                    Some(_) => return Err(BadRequest::Params),
+                    None => similar::THRESHOLD,
+                };
+                sql::Duplicates::find(term, threshold, conn).await.map(Body::Duplicates).map_err(|_| BadRequest::DB)
+            },
+            ("PUT", Some(subject), Some(a), Some(b), None) => {
+                let pupils = match (a.parse(), b.parse()) {
+                    (Ok(a), Ok(b)) => (a, b),
+                    _ => return Err(BadRequest::Params),
+                };
+                self.existing_subject(subject, &mut conn).await?;
+                match sql::Duplicates::review(term, subject, pupils, teacher, &mut conn).await {
+                    Ok(true) => sql::Duplicates::get(term, None, &mut conn).await.map(Body::Duplicates).map_err(|_| BadRequest::DB),
+                    Ok(false) => Err(BadRequest::NotFound),
+                    Err(_) => Err(BadRequest::DB),
+                }
+            },
+            _ => Err(BadRequest::NotFound),
+        }
+    }
+
+    // Report cards as pdfs for the pupils the teacher can see, with the approved and published reports which go home
+    // or ?status=<status> for only reports at that status:
+    // cards+pupil+<id>+<term> <- one pupil's, cards+class+<class>+<term> <- the class's in one pdf, or with ?zip=true a pdf each.
+    // ?format=html fills in the school's HTML template instead: a pupil's is sent as the page, a class's as a zip of pages.
+    // Cards with text Helvetica can't print, names like Łukasz, are left out of pdfs and listed in X-Not-Printed,
+    // and in not_printed.json in a zip: HTML cards print them. Everything is refused when it's the school's own text
+    // on every card, or there'd be no card left.
+    pub async fn cards(&self, school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let (by, name, term) = match (params.next(), params.next(), params.next(), params.next()) {
+            (Some(by), Some(name), Some(term), None) => (by, name, term),
+            _ => return Err(BadRequest::Params),
+        };
+        if !sql::TERMS.contains(&term) { return Err(BadRequest::NoTerm) }
+        let status = match self.query("status").map(str::parse) {
+            Some(Ok(status)) => Some(status),
+            Some(Err(_)) => return Err(BadRequest::Status),
+            None => None,
+        };
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        let (pupils, file_name) = match by {
+            "pupil" => {
+                let pupil = self.get_pupil(name.parse().map_err(|_| BadRequest::Params)?, &mut conn).await?;
+                self.tutor(teacher, role, pupil.class(), &mut conn).await?;
+                let name = format!("{}_{}", pupil.id(), term);
+                (vec![pupil], name)
+            },
+            "class" => {
+                self.existing_class(name, &mut conn).await?;
+                self.tutor(teacher, role, name, &mut conn).await?;
+                let roll = sql::Class::new(name.to_string()).roll(&mut conn).await.map_err(|_| BadRequest::DB)?;
+                (roll, format!("{}_{}", name, term))
+            },
+            _ => return Err(BadRequest::Params),
+        };
+
+        let cards = sql::Pupils::new(pupils).cards(term, status, &mut conn).await.map_err(|_| BadRequest::DB)?;
+        if self.query("format") == Some("html") {
+            let template = sql::School::new(school.to_string()).html(&mut conn).await.map_err(|_| BadRequest::DB)?;
+            let mut pages = vec![];
+            for card in &cards {
+                let page = html::render(&template, &html::context(card, school)).map_err(BadRequest::Html)?;
+                pages.push((card.file_name("html"), page.into_bytes()));
+            }
+            let file = match (by, pages.pop()) {
+                ("pupil", Some((_, page))) => File { name: None, kind: "text/html; charset=utf-8", bytes: page },
+                (_, last) => {
+                    pages.extend(last);
+                    File { name: Some(format!("{}.zip", file_name)), kind: "application/zip", bytes: zip::zip(&pages) }
+                },
+            };
+            return Ok(Body::File(file));
+        }
+
+        let layout = sql::School::new(school.to_string()).layout(&mut conn).await.map_err(|_| BadRequest::DB)?;
+        let unprintable = card::unprintable(&cards, &layout, school);
+        let cards: Vec<card::Card> = cards.into_iter()
+            .filter(|card| !unprintable.iter().any(|found| found.pupil_id == Some(card.pupil_id))).collect();
+        if unprintable.iter().any(|found| found.pupil_id.is_none()) || !unprintable.is_empty() && cards.is_empty() {
+            return Err(BadRequest::Unprintable(unprintable))
+        }
+        let file = if by == "class" && self.query("zip") == Some("true") {
+            let mut files: Vec<(String, Vec<u8>)> = cards.iter()
+                .map(|card| (card.file_name("pdf"), card::render(std::slice::from_ref(card), &layout, school)))
+                .collect();
+            if !unprintable.is_empty() { files.push(("not_printed.json".to_string(), serde_json::to_vec_pretty(&unprintable).unwrap_or_default())) }
+            File { name: Some(format!("{}.zip", file_name)), kind: "application/zip", bytes: zip::zip(&files) }
+        } else {
+            File { name: Some(format!("{}.pdf", file_name)), kind: "application/pdf", bytes: card::render(&cards, &layout, school) }
+        };
+        Ok(Body::Cards(file, unprintable))
+    }
+
+    // Reports as a spreadsheet, a row for each pupil and a column for each subject and term, or only ?subject= and ?term=,
+    // as csv or with ?format=xlsx a workbook: export+class+<class> <- the class,
+    // export+year+<year> <- pupils born in the school year starting that September, heads of year and above only
+    pub async fn export(&self, _school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let (by, name) = match (params.next(), params.next(), params.next()) {
+            (Some(by), Some(name), None) => (by, name),
+            _ => return Err(BadRequest::Params),
+        };
+        let format = match self.query("format").map(str::parse) {
+            Some(Ok(format)) => format,
+            Some(Err(_)) => return Err(BadRequest::Params),
+            None => export::Format::Csv,
+        };
+        let (subject, term) = (self.query("subject").filter(|subject| !subject.is_empty()), self.query("term").filter(|term| !term.is_empty()));
+        if term.is_some_and(|term| !sql::TERMS.contains(&term)) { return Err(BadRequest::NoTerm) }
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        if let Some(subject) = subject { self.existing_subject(subject, &mut conn).await? }
+        let export = sql::Export::new(subject.map(str::to_string), term.map(str::to_string), &mut conn).await.map_err(|_| BadRequest::DB)?;
+        let export = match by {
+            "class" => {
+                self.existing_class(name, &mut conn).await?;
+                match subject {
+                    Some(subject) => self.access(teacher, role, name, Some(subject), &mut conn).await?,
+                    None => self.tutor(teacher, role, name, &mut conn).await?,
+                }
+                export.class(name.to_string())
+            },
+            "year" => {
+                if role < sql::Role::HeadOfYear { return Err(BadRequest::Forbidden) }
+                export.year(name.parse().map_err(|_| BadRequest::Params)?)
+            },
+            _ => return Err(BadRequest::Params),
+        };
+        if export.too_wide() { return Err(BadRequest::Params) }
+
+        let name = format!("{}_{}_reports.{}", by, name, format.extension());
+        Ok(Body::Spreadsheet(Spreadsheet { name, format, export }))
+    }
+
+    // a pupil's card through an HTML template, to try one out before it's saved or the saved one without a template:
+    // preview <- body: html::Preview
+    pub async fn preview(&self, school: &str, teacher: &str) -> Result<Body, BadRequest> {
+        let preview: html::Preview = self.json()?;
+        if !sql::TERMS.contains(&preview.term.as_str()) { return Err(BadRequest::NoTerm) }
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        let pupil = self.get_pupil(preview.pupil_id, &mut conn).await?;
+        self.tutor(teacher, role, pupil.class(), &mut conn).await?;
+        let template = match preview.template {
+            Some(template) => template,
+            None => sql::School::new(school.to_string()).html(&mut conn).await.map_err(|_| BadRequest::DB)?,
+        };
+
+        let cards = sql::Pupils::new(vec![pupil]).cards(&preview.term, None, &mut conn).await.map_err(|_| BadRequest::DB)?;
+        let card = cards.first().ok_or(BadRequest::NotFound)?;
+        let page = html::render(&template, &html::context(card, school)).map_err(BadRequest::Html)?;
+        Ok(Body::File(File { name: None, kind: "text/html; charset=utf-8", bytes: page.into_bytes() }))
+    }
+
+    // the tutor's comment printed on a pupil's report card, from their tutor or a head of year: tutor <- body: TutorComment
+    pub async fn tutor_comment(&self, school: &str, teacher: &str) -> Result<Body, BadRequest> {
+        let mut comment: sql::TutorComment = self.json()?;
+        if !sql::TERMS.contains(&comment.term()) { return Err(BadRequest::NoTerm) }
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        let pupil = self.get_pupil(comment.pupil_id(), &mut conn).await?;
+        if role < sql::Role::HeadOfYear {
+            match sql::Teacher::new(teacher.to_string()).tutors(pupil.class(), &mut conn).await {
+                Ok(true) => (),
+                Ok(false) => return Err(BadRequest::Forbidden),
+                Err(_) => return Err(BadRequest::DB),
+            }
+        }
+
+        let limits = sql::School::new(school.to_string()).limits(&mut conn).await.map_err(|_| BadRequest::DB)?;
+        let content = validate::content(comment.content(), &limits).map_err(BadRequest::Text)?;
+        comment.set_content(content);
+        comment.save(teacher, &mut conn).await.map_err(|_| BadRequest::DB)?;
+        Ok(Body::TutorComment(comment))
+    }
+
+    // comments, comments+<subject> <- GET the teacher's bank, POST comments <- add the entry in the body,
+    // PUT comments+<id> <- replace it with the body, DELETE comments+<id>
+    pub async fn comments(&self, _school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+
+        match (self.method.as_str(), params.next()) {
+            ("GET", subject) => {
+                if let Some(subject) = subject { self.existing_subject(subject, &mut conn).await? }
+                sql::Comments::new(teacher, subject, &mut conn).await.map(Body::Comments).map_err(|_| BadRequest::DB)
+            },
+            ("POST", None) => {
+                let mut comment: sql::Comment = self.json()?;
+                self.bank_entry(teacher, role, &mut comment, &mut conn).await?;
+                comment.add(&mut conn).await.map_err(|_| BadRequest::DB)?;
+                Ok(Body::Comment(comment))
+            },
+            ("PUT", Some(id)) => {
+                let id = id.parse().map_err(|_| BadRequest::Params)?;
+                let current = self.get_comment(id, &mut conn).await?;
+                self.may_edit(teacher, role, &current, &mut conn).await?;
+                let mut comment: sql::Comment = self.json()?;
+                self.bank_entry(teacher, role, &mut comment, &mut conn).await?;
+                comment.update(id, &mut conn).await.map_err(|_| BadRequest::DB)?;
+                Ok(Body::Comment(comment))
+            },
+            ("DELETE", Some(id)) => {
+                let id = id.parse().map_err(|_| BadRequest::Params)?;
+                let current = self.get_comment(id, &mut conn).await?;
+                self.may_edit(teacher, role, &current, &mut conn).await?;
+                sql::Comment::delete(id, &mut conn).await.map_err(|_| BadRequest::DB)?;
+                Ok(Body::Deleted(id.to_string()))
+            },
+            _ => Err(BadRequest::NotFound),
+        }
+    }
+
+    async fn get_comment(&self, id: usize, conn: &mut mysql_async::Conn) -> Result<sql::Comment, BadRequest> {
+        match sql::Comment::get(id, conn).await {
+            Ok(Some(comment)) => Ok(comment),
+            Ok(None) => Err(BadRequest::NotFound),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
+    // entries belong to their teacher, a department's bank to everyone in the department, and admins can change any
+    async fn may_edit(&self, teacher: &str, role: sql::Role, comment: &sql::Comment, conn: &mut mysql_async::Conn) -> Result<(), BadRequest> {
+        if role >= sql::Role::Admin { return Ok(()) }
+        match (comment.teacher(), comment.subject()) {
+            (Some(owner), _) if owner == teacher => Ok(()),
+            (None, Some(subject)) => match sql::Teacher::new(teacher.to_string()).in_department(subject, conn).await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(BadRequest::Forbidden),
+                Err(_) => Err(BadRequest::DB),
+            },
+            _ => Err(BadRequest::Forbidden),
+        }
+    }
+
+    // an entry from a body is kept in a bank the teacher can change, and its text is a template within a report's length
+    async fn bank_entry(&self, teacher: &str, role: sql::Role, comment: &mut sql::Comment, conn: &mut mysql_async::Conn) -> Result<(), BadRequest> {
+        if comment.teacher().is_none() && comment.subject().is_none() { return Err(BadRequest::TermSubject) }
+        if let Some(subject) = comment.subject() { self.existing_subject(subject, conn).await? }
+        self.may_edit(teacher, role, comment, conn).await?;
+        let text = validate::content(comment.text(), &validate::Limits::default()).map_err(BadRequest::Text)?;
+        template::check(&text).map_err(BadRequest::Template)?;
+        comment.set_text(text);
+        Ok(())
+    }
+
+    // a bank entry or template in the body filled in for a pupil the teacher can see
+    pub async fn render(&self, _school: &str, teacher: &str) -> Result<Body, BadRequest> {
+        let render: template::Render = self.json()?;
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        self.subject_term(&render.subject, &render.term, &mut conn).await?;
+        let pupil = self.get_pupil(render.pupil_id, &mut conn).await?;
+        self.access(teacher, role, pupil.class(), Some(&render.subject), &mut conn).await?;
+
+        let text = match (render.comment, render.template) {
+            (Some(id), None) => self.get_comment(id, &mut conn).await?.text().to_string(),
+            (None, Some(template)) => template,
+            _ => return Err(BadRequest::Body),
+        };
+        let mut values = pupil_values(&pupil, render.values);
+        values.insert("subject".to_string(), render.subject);
+        values.insert("term".to_string(), render.term);
+        template::render(&text, &values, &pupil.pronouns()).map(Body::Text).map_err(BadRequest::Template)
+    }
+
+    // POST pupil+<id>+text <- body: Text, a template filled in with the pupil's names and pronouns
+    pub async fn pupil_text(&self, _school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let id = if let Some(Ok(id)) = params.next().map(str::parse) { id }
+            else { return Err(BadRequest::Params) };
+        if params.next() != Some("text") { return Err(BadRequest::Params) }
+        let text: template::Text = self.json()?;
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        let pupil = self.get_pupil(id, &mut conn).await?;
+        self.access(teacher, role, pupil.class(), None, &mut conn).await?;
+
+        let values = pupil_values(&pupil, text.values);
+        template::render(&text.template, &values, &pupil.pronouns()).map(Body::Text).map_err(BadRequest::Template)
+    }
+
+    pub async fn home(&self, _school: &str, teacher: &str) -> Result<Body, BadRequest> {
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        self.role(teacher, &mut conn).await?;
+
+        match sql::Home::new(teacher.to_string(), conn).await {
+            Ok(home) => Ok(Body::Home(home)),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
+    // moves a report through draft -> submitted -> approved/returned -> published
+    pub async fn status(&self, _school: &str, teacher: &str) -> Result<Body, BadRequest> {
+        let mut change: sql::StatusChange = self.json()?;
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        let pupil = self.get_pupil(change.pupil_id(), &mut conn).await?;
+        if role < sql::Role::HeadOfYear {
+            self.writes(teacher, role, pupil.class(), change.subject(), &mut conn).await?;
+        }
+
+        // a submitted report is linted for the writer to look over, nothing the linter finds is certain enough to stop it
+        if change.status() == sql::Status::Submitted {
+            self.subject_term(change.subject(), change.term(), &mut conn).await?;
+            let content = change.content(&mut conn).await.map_err(|_| BadRequest::DB)?;
+            change.warn(self.lint_report(&pupil, &content, &mut conn).await?);
+        }
+
+        change.apply(role, &mut conn).await?;
+        Ok(Body::StatusChange(change))
+    }
+
+    // words in the body's content neither the dictionary nor the school knows: spelling <- body: Check
+    pub async fn spelling(&self, school: &str, teacher: &str) -> Result<Body, BadRequest> {
+        let check: spell::Check = self.json()?;
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        self.role(teacher, &mut conn).await?;
+        let words = sql::School::new(school.to_string()).dictionary(&mut conn).await.map_err(|_| BadRequest::DB)?;
+        Ok(Body::Misspellings(spell::Checker::new(words).check(&check.content)))
+    }
+
+    // the issues in a report's content, which needn't have been saved: lint <- body: Report
+    pub async fn lint(&self, _school: &str, teacher: &str) -> Result<Body, BadRequest> {
+        let report: sql::Report = self.json()?;
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        self.subject_term(report.subject(), report.term(), &mut conn).await?;
+        let pupil = self.get_pupil(report.pupil_id(), &mut conn).await?;
+        self.access(teacher, role, pupil.class(), Some(report.subject()), &mut conn).await?;
+
+        self.lint_report(&pupil, report.content(), &mut conn).await.map(Body::Issues)
+    }
+
+    // checked against the names of everyone else in the pupil's class
+    async fn lint_report(&self, pupil: &sql::Pupil, content: &str, conn: &mut mysql_async::Conn) -> Result<Vec<lint::Issue>, BadRequest> {
+        let roll = if pupil.class().is_empty() { vec![] }
+            else { sql::Class::new(pupil.class().to_string()).roll(conn).await.map_err(|_| BadRequest::DB)? };
+        let classmates = roll.iter().filter(|other| other.id() != pupil.id())
+            .flat_map(|other| [other.known_as(), other.first_name()]).collect();
+        let names = vec![pupil.known_as(), pupil.first_name(), pupil.last_name()];
+        Ok(lint::lint(content, &lint::Pupil { names, pronouns: pupil.recorded_pronouns(), classmates }))
+    }
+
+    // saves one report, sent with the version it was read at: report <- body: Report
+    pub async fn save_report(&self, school: &str, teacher: &str) -> Result<Body, BadRequest> {
+        let mut report: sql::Report = self.json()?;
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        self.subject_term(report.subject(), report.term(), &mut conn).await?;
+        let pupil = self.get_pupil(report.pupil_id(), &mut conn).await?;
+        self.writes(teacher, role, pupil.class(), report.subject(), &mut conn).await?;
+
+        let limits = sql::School::new(school.to_string()).limits(&mut conn).await.map_err(|_| BadRequest::DB)?;
+        report.validate(&limits).map_err(BadRequest::Invalid)?;
+
+        report.update(conn).await?;
+        Ok(Body::Report(report))
+    }
+
+    // saves a class's reports for one subject and term together: class+<id>+reports+<subject>+<term> <- body: Reports
+    pub async fn save_reports(&self, school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let (class, subject, term) = match (params.next(), params.next(), params.next(), params.next(), params.next()) {
+            (Some(class), Some("reports"), Some(subject), Some(term), None) => (sql::Class::new(class.to_string()), subject, term),
+            _ => return Err(BadRequest::Params),
+        };
+        let mut reports: sql::Reports = self.json()?;
+        if !reports.iter().all(|report| report.subject() == subject && report.term() == term) { return Err(BadRequest::TermSubject) }
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        let role = self.role(teacher, &mut conn).await?;
+        self.subject_term(subject, term, &mut conn).await?;
+        self.writes(teacher, role, class.name(), subject, &mut conn).await?;
+
+        let pupils = class.pupil_ids(&mut conn).await.map_err(|_| BadRequest::Pupils)?;
+        if !reports.iter().all(|report| pupils.contains(&report.pupil_id())) { return Err(BadRequest::Pupils) }
+
+        let limits = sql::School::new(school.to_string()).limits(&mut conn).await.map_err(|_| BadRequest::DB)?;
+        reports.validate(&limits).map_err(BadRequest::Invalid)?;
+
+        reports.update(conn).await?;
+        Ok(Body::Reports(reports))
+    }
+
+    // admin only: assignments+<teacher>
+    pub async fn assignments(&self, _school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let of = if let Some(of) = params.next() { of }
+            else { return Err(BadRequest::Params) };
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        self.admin(teacher, &mut conn).await?;
+
+        match sql::Assignments::new(of, &mut conn).await {
+            Ok(assignments) => Ok(Body::Assignments(assignments)),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
+    // admin only, adds or removes the assignment in the body
+    pub async fn assign(&self, _school: &str, teacher: &str, add: bool) -> Result<Body, BadRequest> {
+        let assignment: sql::Assignment = self.json()?;
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        self.admin(teacher, &mut conn).await?;
+
+        let done = if add { assignment.add(&mut conn).await } else { assignment.remove(&mut conn).await };
+        match done {
+            Ok(true) => Ok(Body::Assignment(assignment)),
+            Ok(false) => Err(BadRequest::Body),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
+    // Admin only, the method says what to do:
+    // admin+pupils, admin+classes, admin+subjects <- GET lists, POST creates from body
+    // admin+pupil+<id>, admin+class+<name>, admin+subject+<name> <- GET reads, PUT updates from body, DELETE deletes
+    // admin+transfer <- POST moves a pupil between classes and sets, admin+history+<id> <- GET their moves
+    // admin+import <- POST plans importing pupils from an MIS csv, body: import::Import, admin+import+commit carries it out
+    // admin+backup <- GET everything as one json file, admin+restore <- POST loads one into a database with no school yet
+    // admin+access+<id> <- POST exports everything held about a pupil as a zip, for a subject access request, and logs that it did
+    // GET admin+pupils finds pupils with ?name=&fuzzy=&class=&born=, and lists of pupils page with ?after=&limit=
+    // admin+words <- GET and POST the spell checker's extra words, DELETE admin+word+<word>
+    // admin+layout <- GET the school's report card layout, PUT replaces it
+    // admin+html <- GET the school's HTML report card template, PUT replaces it with the json string in the body once it's checked
+    pub async fn manage(&self, school: &str, teacher: &str, mut params: Split<'_, char>) -> Result<Body, BadRequest> {
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        self.admin(teacher, &mut conn).await?;
+
+        match (self.method.as_str(), params.next(), params.next()) {
+            ("GET", Some("pupils"), None) => {
+                let (search, page) = (self.pupil_search()?, self.page()?);
+                sql::Pupils::search(&search, &page, &mut conn).await.map(Body::Pupils).map_err(|_| BadRequest::DB)
+            },
+            ("POST", Some("pupils"), None) => {
+                let mut pupils: sql::Pupils = self.json()?;
+                if !pupils.tidy_pronouns() { return Err(BadRequest::Pronouns) }
+                for class in pupils.classes().filter(|class| !class.is_empty()) {
+                    self.existing_class(class, &mut conn).await?;
+                }
+                pupils.add(conn).await.map_err(|_| BadRequest::DB)?;
+                Ok(Body::Pupils(pupils))
+            },
+            ("GET", Some("pupil"), Some(id)) => {
+                let id = id.parse().map_err(|_| BadRequest::Params)?;
+                Ok(Body::Pupil(self.get_pupil(id, &mut conn).await?))
+            },
+            ("PUT", Some("pupil"), Some(id)) => {
+                let mut pupil: sql::Pupil = self.json()?;
+                if id.parse() != Ok(pupil.id()) { return Err(BadRequest::Params) }
+                if !pupil.tidy_pronouns() { return Err(BadRequest::Pronouns) }
+                pupil.update(conn).await?;
+                Ok(Body::Pupil(pupil))
+            },
+            ("DELETE", Some("pupil"), Some(id)) => {
+                let id = id.parse().map_err(|_| BadRequest::Params)?;
+                match sql::Pupil::delete(id, conn).await {
+                    Ok(true) => Ok(Body::Deleted(id.to_string())),
+                    Ok(false) => Err(BadRequest::NotFound),
+                    Err(_) => Err(BadRequest::DB),
+                }
+            },
+            ("POST", Some("transfer"), None) => {
+                let transfer: sql::Transfer = self.json()?;
+                if !validate::date(transfer.effective()) { return Err(BadRequest::Date) }
+                for subject in transfer.sets() {
+                    self.existing_subject(subject, &mut conn).await?;
+                }
+                transfer.apply(teacher, conn).await?;
+                Ok(Body::Transfer(transfer))
+            },
+            ("POST", Some("import"), commit) => {
+                let import: import::Import = self.json()?;
+                let records = import::records(&import).map_err(BadRequest::Import)?;
+                match commit {
+                    None => {
+                        let (existing, classes) = sql::Pupils::records(&mut conn).await.map_err(|_| BadRequest::DB)?;
+                        Ok(Body::Import(import::plan(records, &existing, &classes)))
+                    },
+                    Some("commit") => Ok(Body::Import(sql::Pupils::import(records, import.skip_conflicts, teacher, conn).await?)),
+                    Some(_) => Err(BadRequest::Params),
+                }
+            },
+            ("GET", Some("backup"), None) => {
+                let backup = sql::DB::backup(school, &mut conn).await.map_err(|_| BadRequest::DB)?;
+                let bytes = serde_json::to_vec(&backup).map_err(|_| BadRequest::DB)?;
+                let name = format!("{}_backup_v{}.json", school, backup::VERSION);
+                Ok(Body::File(File { name: Some(name), kind: "application/json", bytes }))
+            },
+            ("POST", Some("restore"), None) => {
+                let backup: backup::Backup = self.json()?;
+                Ok(Body::Restored(sql::DB::restore(&backup, conn).await?))
+            },
+            ("POST", Some("access"), Some(id)) => {
+                let id = id.parse().map_err(|_| BadRequest::Params)?;
+                let access = sql::Pupil::access(id, teacher, school, conn).await.map_err(|_| BadRequest::DB)?.ok_or(BadRequest::NotFound)?;
+                let name = format!("{}_pupil_{}_records.zip", school, id);
+                Ok(Body::File(File { name: Some(name), kind: "application/zip", bytes: access.archive() }))
+            },
+            ("GET", Some("history"), Some(id)) => {
+                let id = id.parse().map_err(|_| BadRequest::Params)?;
+                self.get_pupil(id, &mut conn).await?;
+                sql::Pupil::history(id, &mut conn).await.map(Body::History).map_err(|_| BadRequest::DB)
+            },
+            ("GET", Some("classes"), None) => sql::Class::all(&mut conn).await.map(Body::Names).map_err(|_| BadRequest::DB),
+            ("POST", Some("classes"), None) => {
+                let class: sql::Class = self.json()?;
+                if !table_name(class.name()) { return Err(BadRequest::Name) }
+                class.add_class(conn).await.map_err(|err| match err {
+                    sql::AdminError::DB(_) => BadRequest::InvalidGroup,
+                    err => err.into(),
+                })?;
+                Ok(Body::Class(class))
+            },
+            ("GET", Some("class"), Some(name)) => {
+                self.existing_class(name, &mut conn).await?;
+                let page = self.page()?;
+                sql::Class::new(name.to_string()).pupils(&page, conn).await.map(Body::Pupils).map_err(|_| BadRequest::DB)
+            },
+            ("PUT", Some("class"), Some(name)) => {
+                let class: sql::Class = self.json()?;
+                if class.name() != name { return Err(BadRequest::Params) }
+                self.existing_class(name, &mut conn).await?;
+                class.set_pupils(conn).await?;
+                Ok(Body::Class(class))
+            },
+            ("DELETE", Some("class"), Some(name)) => {
+                self.existing_class(name, &mut conn).await?;
+                sql::Class::new(name.to_string()).delete(conn).await.map_err(|_| BadRequest::DB)?;
+                Ok(Body::Deleted(name.to_string()))
+            },
+            ("GET", Some("words"), None) => sql::School::new(school.to_string()).words(&mut conn).await.map(Body::Names).map_err(|_| BadRequest::DB),
+            ("POST", Some("words"), None) => {
+                let words: Vec<String> = self.json()?;
+                if !words.iter().all(|word| dictionary_word(word)) { return Err(BadRequest::Name) }
+                sql::School::new(school.to_string()).add_words(&words, &mut conn).await.map_err(|_| BadRequest::DB)?;
+                Ok(Body::Names(words))
+            },
+            ("DELETE", Some("word"), Some(word)) => {
+                let word = decode(word).ok_or(BadRequest::Params)?;
+                match sql::School::new(school.to_string()).remove_word(&word, &mut conn).await {
+                    Ok(true) => Ok(Body::Deleted(word)),
+                    Ok(false) => Err(BadRequest::NotFound),
+                    Err(_) => Err(BadRequest::DB),
+                }
+            },
+            ("GET", Some("layout"), None) => sql::School::new(school.to_string()).layout(&mut conn).await.map(Body::Layout).map_err(|_| BadRequest::DB),
+            ("PUT", Some("layout"), None) => {
+                let layout: card::Layout = self.json()?;
+                if !layout.valid() { return Err(BadRequest::Body) }
+                sql::School::new(school.to_string()).set_layout(&layout, &mut conn).await.map_err(|_| BadRequest::DB)?;
+                Ok(Body::Layout(layout))
+            },
+            ("GET", Some("html"), None) => sql::School::new(school.to_string()).html(&mut conn).await.map(Body::Text).map_err(|_| BadRequest::DB),
+            ("PUT", Some("html"), None) => {
+                let template: String = self.json()?;
+                html::check(&template).map_err(BadRequest::Html)?;
+                sql::School::new(school.to_string()).set_html(&template, &mut conn).await.map_err(|_| BadRequest::DB)?;
+                Ok(Body::Text(template))
+            },
+            ("GET", Some("subjects"), None) => sql::Subject::all(&mut conn).await.map(Body::Names).map_err(|_| BadRequest::DB),
+            ("POST", Some("subjects"), None) => {
+                let subject: sql::Subject = self.json()?;
+                if !table_name(subject.name()) || sql::TERMS.contains(&subject.name()) { return Err(BadRequest::Name) }
+                subject.add_subject(conn).await.map_err(|err| match err {
+                    sql::AdminError::DB(_) => BadRequest::TermSubject,
+                    err => err.into(),
+                })?;
+                Ok(Body::Subject(subject))
+            },
+            ("GET", Some("subject"), Some(name)) => {
+                let subject = sql::Subject::new(name.to_string());
+                self.existing_subject(name, &mut conn).await?;
+                subject.pupils(&mut conn).await.map(Body::Subject).map_err(|_| BadRequest::DB)
+            },
+            ("PUT", Some("subject"), Some(name)) => {
+                let subject: sql::Subject = self.json()?;
+                if subject.name() != name { return Err(BadRequest::Params) }
+                self.existing_subject(name, &mut conn).await?;
+                subject.set_pupils(conn).await?;
+                Ok(Body::Subject(subject))
+            },
+            ("DELETE", Some("subject"), Some(name)) => {
+                self.existing_subject(name, &mut conn).await?;
+                sql::Subject::new(name.to_string()).delete(conn).await.map_err(|_| BadRequest::DB)?;
+                Ok(Body::Deleted(name.to_string()))
+            },
+            _ => Err(BadRequest::NotFound),
+        }
+    }
+
+    async fn existing_class(&self, class: &str, conn: &mut mysql_async::Conn) -> Result<(), BadRequest> {
+        match sql::Class::new(class.to_string()).exists(conn).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(BadRequest::InvalidGroup),
+            Err(_) => Err(BadRequest::DB),
+        }
+    }
+
+    // checks reports against the school's limits without saving, returning them normalised
+    pub async fn validate(&self, school: &str, teacher: &str) -> Result<Body, BadRequest> {
+        let mut reports: sql::Reports = self.json()?;
+
+        let mut conn = if let Ok(conn) = sql::DB::new().await { conn.conn() } 
+            else { return Err(BadRequest::DB) };
+        self.role(teacher, &mut conn).await?;
+        let limits = sql::School::new(school.to_string()).limits(&mut conn).await.map_err(|_| BadRequest::DB)?;
+
+        reports.validate(&limits).map_err(BadRequest::Invalid)?;
+        Ok(Body::Reports(reports))
     }
 
 }
 
 impl HttpResponse {
 
-    pub async fn build(request: HttpRequest) -> Result<(HttpResponse, TcpStream), BadRequest> {
-        match request.method.as_str() {
-            "GET" => HttpResponse::get(request).await,
-            "POST" => HttpResponse::post(request).await,
+    pub async fn build(request: HttpRequest) -> (HttpResponse, TcpStream) {
+        let body = match request.method.as_str() {
+            "GET" => HttpResponse::get(&request).await,
+            "POST" => HttpResponse::post(&request).await,
+            "PUT" | "DELETE" => HttpResponse::manage(&request).await,
             _ => {
                 println!("404 METHOD");
                 Err(BadRequest::NotFound)
             },
-        }
+        };
+
+        let response = match body {
+            Ok(Body::File(file)) => HttpResponse::file(file),
+            Ok(Body::Cards(file, unprintable)) => HttpResponse::file(file).not_printed(&unprintable),
+            Ok(Body::Spreadsheet(spreadsheet)) => HttpResponse::spreadsheet(spreadsheet),
+            Ok(body) => HttpResponse::new("200 OK", HttpResponse::body(body)),
+            Err(err) => HttpResponse::new(err.status(), HttpResponse::body(err)),
+        };
+        (response, request.stream)
+    }
+
+    // the answer to a request which couldn't be read, which there's no route for
+    pub fn unreadable(err: &parse_connection::RequestError) -> HttpResponse {
+        let status = match err {
+            parse_connection::RequestError::PostTooLarge => "413 Payload Too Large",
+            parse_connection::RequestError::Timeout => "408 Request Timeout",
+            _ => BadRequest::Unreadable.status(),
+        };
+        HttpResponse::new(status, HttpResponse::body(BadRequest::Unreadable))
+    }
+
+    fn new(status: &str, body: String) -> HttpResponse {
+        let length = body.len();
+
+        let (status, version) = (status.to_string(), "HTTP/1.1".to_string());
+        let headers = vec![format!("Content-Length: {}", length), "Content-Type: application/json".to_string()];
+
+        HttpResponse { status, version, headers, body: body.into_bytes(), spreadsheet: None }
+    }
+
+    fn file(file: File) -> HttpResponse {
+        let mut headers = vec![format!("Content-Length: {}", file.bytes.len()), format!("Content-Type: {}", file.kind)];
+        if let Some(name) = file.name { headers.push(format!("Content-Disposition: attachment; filename=\"{}\"", name)) }
+        HttpResponse { status: "200 OK".to_string(), version: "HTTP/1.1".to_string(), headers, body: file.bytes, spreadsheet: None }
+    }
+
+    // the pupils whose cards were left out, as ids since their names may not be ASCII
+    fn not_printed(mut self, unprintable: &[card::Unprintable]) -> HttpResponse {
+        let ids: Vec<String> = unprintable.iter().filter_map(|found| found.pupil_id).map(|id| id.to_string()).collect();
+        if !ids.is_empty() { self.headers.push(format!("X-Not-Printed: {}", ids.join(", "))) }
+        self
     }
 
-    async fn get(request: HttpRequest) -> Result<(HttpResponse, TcpStream), BadRequest> {
-        let mut uri = request.uri.split('/');
+    // the length isn't known until the last row's written, so it's sent in chunks
+    fn spreadsheet(spreadsheet: Spreadsheet) -> HttpResponse {
+        let headers = vec![
+            "Transfer-Encoding: chunked".to_string(),
+            format!("Content-Type: {}", spreadsheet.format.kind()),
+            format!("Content-Disposition: attachment; filename=\"{}\"", spreadsheet.name),
+        ];
+        HttpResponse { status: "200 OK".to_string(), version: "HTTP/1.1".to_string(), headers, body: vec![], spreadsheet: Some(spreadsheet) }
+    }
+
+    // splits /<school>/<teacher>/<params> and the params on '+'
+    fn route(request: &HttpRequest) -> Result<(&str, &str, Option<Split<'_, char>>), BadRequest> {
+        let mut uri = request.path().split('/');
         uri.next();
-        /*
-            /<school_name>/<teacher_name>/<pupil>, <class>/<id>/info, reports/<subject>/ <- if reports
-            /<school_name>/<teacher_name>/(params:-)<pupil, class>+<id>+<info, reports>+<subject>+<term> <- if reports
-        */
         let (school, teacher) = if let (Some(school), Some(teacher)) = (uri.next(), uri.next()) { (school, teacher) } 
             else { 
                 println!("404 school teacher");
                 return Err(BadRequest::Login) 
             };
+        Ok((school, teacher, uri.next().map(|params| params.split('+'))))
+    }
 
-        //let params = uri.next().map(|params| params.split('+'));
+    async fn get(request: &HttpRequest) -> Result<Body, BadRequest> {
+        /*
+            /<school_name>/<teacher_name>/<pupil>, <class>/<id>/info, reports/<subject>/ <- if reports
+            /<school_name>/<teacher_name>/(params:-)<pupil, class>+<id>+<info, reports>+<subject>+<term> <- if reports
+            filters go in the query string: ...+<term>?status=<status>
+            /<school_name>/<teacher_name>/search?q=<words>&... <- report content
+            /<school_name>/<teacher_name>/stats+<teacher, class, subject>+<name>+<term> <- report lengths and readability
+            /<school_name>/<teacher_name>/duplicates+<term>?class=<class> <- near-duplicate reports, heads of year only
+            /<school_name>/<teacher_name>/cards+<pupil, class>+<id>+<term> <- report cards as pdf, ?zip=true for a class's as a zip,
+                ?format=html through the school's HTML template
+            /<school_name>/<teacher_name>/export+<class, year>+<name>?format=<csv, xlsx> <- a spreadsheet of reports, a row per pupil
+        */
+        let (school, teacher, params) = HttpResponse::route(request)?;
 
-        let body = if let Some(params) = uri.next() {
-            let mut params = params.split('+');
+        if let Some(mut params) = params {
             match params.next().expect("SPLITTING PARAMS") {
                 "class" => request.class(school, teacher, params).await,
+                "pupil" => request.pupil(school, teacher, params).await,
+                "assignments" => request.assignments(school, teacher, params).await,
+                "admin" => request.manage(school, teacher, params).await,
+                "search" => request.search(school, teacher).await,
+                "comments" => request.comments(school, teacher, params).await,
+                "stats" => request.stats(school, teacher, params).await,
+                "duplicates" => request.duplicates(school, teacher, params).await,
+                "cards" => request.cards(school, teacher, params).await,
+                "export" => request.export(school, teacher, params).await,
                 "" =>  request.home(school, teacher).await,
                 _ => {
                     println!("404 in BODY"); 
-                    return Err(BadRequest::NotFound)
+                    Err(BadRequest::NotFound)
                 },
             }
-        } else { request.home(school, teacher).await };
-
-        // Check for err in body and early return appropriately
-        let body = if let Ok(body) = body { HttpResponse::body(body) }
-            else { return Err(BadRequest::NotFound) };
-
-        let length = body.as_bytes().len();
-
-        let (status, version) = ("200 OK".to_string(), "HTTP/1.1".to_string());
-        let headers = vec![format!("Content-Length: {}", length)];
+        } else { request.home(school, teacher).await }
+    }
 
+    async fn post(request: &HttpRequest) -> Result<Body, BadRequest> {
+        /*
+            /<school_name>/<teacher_name>/report <- body: Report
+            /<school_name>/<teacher_name>/class+<id>+reports+<subject>+<term> <- body: Reports
+            /<school_name>/<teacher_name>/status <- body: StatusChange
+            /<school_name>/<teacher_name>/validate <- body: Reports
+            /<school_name>/<teacher_name>/lint <- body: Report
+            /<school_name>/<teacher_name>/spelling <- body: Check
+            /<school_name>/<teacher_name>/assign, unassign <- body: Assignment
+            /<school_name>/<teacher_name>/comments <- body: Comment
+            /<school_name>/<teacher_name>/render <- body: Render
+            /<school_name>/<teacher_name>/pupil+<id>+text <- body: Text
+            /<school_name>/<teacher_name>/duplicates+<term>?threshold=<0.5 to 1> <- compares the term's reports
+            /<school_name>/<teacher_name>/tutor <- body: TutorComment
+            /<school_name>/<teacher_name>/preview <- body: html::Preview, a card through an HTML template
+        */
+        let (school, teacher, params) = HttpResponse::route(request)?;
 
-        Ok( (HttpResponse { status, version, headers, body }, request.stream) )
+        let mut params = if let Some(params) = params { params }
+            else { return Err(BadRequest::NotFound) };
+        match params.next() {
+            Some("report") => request.save_report(school, teacher).await,
+            Some("class") => request.save_reports(school, teacher, params).await,
+            Some("status") => request.status(school, teacher).await,
+            Some("validate") => request.validate(school, teacher).await,
+            Some("assign") => request.assign(school, teacher, true).await,
+            Some("unassign") => request.assign(school, teacher, false).await,
+            Some("admin") => request.manage(school, teacher, params).await,
+            Some("comments") => request.comments(school, teacher, params).await,
+            Some("render") => request.render(school, teacher).await,
+            Some("lint") => request.lint(school, teacher).await,
+            Some("spelling") => request.spelling(school, teacher).await,
+            Some("pupil") => request.pupil_text(school, teacher, params).await,
+            Some("duplicates") => request.duplicates(school, teacher, params).await,
+            Some("tutor") => request.tutor_comment(school, teacher).await,
+            Some("preview") => request.preview(school, teacher).await,
+            _ => Err(BadRequest::NotFound),
+        }
     }
 
-    async fn post(request: HttpRequest) -> Result<(HttpResponse, TcpStream), BadRequest> {
-        println!("{}", request.body);
-        todo!()
+    // only the admin, comment bank and duplicate review routes take PUT and DELETE
+    async fn manage(request: &HttpRequest) -> Result<Body, BadRequest> {
+        let (school, teacher, params) = HttpResponse::route(request)?;
+
+        let mut params = if let Some(params) = params { params }
+            else { return Err(BadRequest::NotFound) };
+        match params.next() {
+            Some("admin") => request.manage(school, teacher, params).await,
+            Some("comments") => request.comments(school, teacher, params).await,
+            Some("duplicates") => request.duplicates(school, teacher, params).await,
+            _ => Err(BadRequest::NotFound),
+        }
     }
 
     fn body<T: Serialize>(body: T) -> String {
@@ -170,9 +1341,82 @@ impl HttpResponse {
     }
 
     pub async fn write(self, mut stream: TcpStream) -> tokio::io::Result<()> {
-        let response = format!("{} {}\r\n{}\r\n\r\n{}\r\n\r\n", self.version, self.status, self.headers.join("\r\n"), self.body);
-        stream.write_all(response.as_bytes()).await?;
+        let head = format!("{} {}\r\n{}\r\n\r\n", self.version, self.status, self.headers.join("\r\n"));
+        stream.write_all(head.as_bytes()).await?;
+        if let Some(spreadsheet) = self.spreadsheet { return spreadsheet.write(&mut stream).await }
+        stream.write_all(&self.body).await?;
+        stream.write_all(b"\r\n\r\n").await?;
+        Ok(())
+    }
+}
+
+impl Spreadsheet {
+    // pieces of the sheet are sent once there's about this much of them
+    const CHUNK: usize = 16 * 1024;
+
+    // The headings, then the rows as they come from the database, in chunks. If the database fails part way
+    // the response ends without its last chunk, so the download fails rather than looking complete.
+    async fn write(self, stream: &mut TcpStream) -> io::Result<()> {
+        let mut sheet = export::Sheet::new(self.format);
+        let mut pending = sheet.start(&self.export.headings());
+        let mut conn = sql::DB::new().await.map_err(io::Error::other)?.conn();
+        let (query, params) = self.export.query();
+        let mut rows = conn.exec_iter(query, params).await.map_err(io::Error::other)?;
+        while let Some(row) = rows.next().await.map_err(io::Error::other)? {
+            pending.extend(sheet.row(&sql::Export::fields(row)));
+            if pending.len() >= Spreadsheet::CHUNK { Spreadsheet::chunk(stream, &mut pending).await? }
+        }
+        pending.extend(sheet.finish());
+        Spreadsheet::chunk(stream, &mut pending).await?;
+        stream.write_all(b"0\r\n\r\n").await
+    }
+
+    async fn chunk(stream: &mut TcpStream, pending: &mut Vec<u8>) -> io::Result<()> {
+        if pending.is_empty() { return Ok(()) }
+        stream.write_all(format!("{:x}\r\n", pending.len()).as_bytes()).await?;
+        stream.write_all(pending).await?;
+        stream.write_all(b"\r\n").await?;
+        pending.clear();
         Ok(())
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{ card::Unprintable, decode, parse_connection::RequestError, table_name, File, HttpResponse };
+
+    #[test]
+    fn query_values_are_decoded() {
+        assert_eq!(decode("Jones"), Some("Jones".to_string()));
+        assert_eq!(decode("O%27Brien+Sam"), Some("O'Brien Sam".to_string()));
+        assert_eq!(decode("Zo%C3%AB"), Some("Zoë".to_string()));
+        assert_eq!(decode("100%"), None);
+        assert_eq!(decode("%zz"), None);
+        assert_eq!(decode("%FF"), None);
+    }
+
+    #[test]
+    fn table_names() {
+        assert!(table_name("French"));
+        assert!(table_name("Year_9A"));
+        assert!(!table_name(""));
+        assert!(!table_name("French; drop table Pupils"));
+        assert!(!table_name("Fran`ais"));
+        assert!(!table_name(&"a".repeat(31)));
+    }
+
+    #[test]
+    fn unreadable_requests_are_answered() {
+        let response = HttpResponse::unreadable(&RequestError::NoRequest);
+        assert_eq!((response.status.as_str(), response.body.as_slice()), ("400 Bad Request", b"\"Unreadable\"".as_slice()));
+        assert_eq!(HttpResponse::unreadable(&RequestError::PostTooLarge).status, "413 Payload Too Large");
+    }
+
+    #[test]
+    fn cards_left_out_are_named() {
+        let file = || File { name: Some("9A_autumn.pdf".to_string()), kind: "application/pdf", bytes: vec![] };
+        let left_out = vec![Unprintable { pupil_id: Some(7), found: "Ł".to_string() }, Unprintable { pupil_id: Some(12), found: "ł".to_string() }];
+        assert!(HttpResponse::file(file()).not_printed(&left_out).headers.contains(&"X-Not-Printed: 7, 12".to_string()));
+        assert!(!HttpResponse::file(file()).not_printed(&[]).headers.iter().any(|header| header.starts_with("X-Not-Printed")));
+    }
+}
@@ -0,0 +1,199 @@
+use serde::Serialize;
+use std::collections::HashSet;
+use crate::{
+    search::Span,
+    template::Pronouns,
+};
+
+// he and she forms, flagged when they aren't the pupil's. They forms aren't as they're used for anyone.
+// Nothing says who a word is about, his father is fine in any report, so like every lint these are for the writer to judge.
+const GENDERED: [&str; 8] = ["he", "him", "his", "himself", "she", "her", "hers", "herself"];
+// left in from drafts and templates, compared lowercased
+const PLACEHOLDERS: [&str; 5] = ["todo", "tbc", "tbd", "lorem", "ipsum"];
+// capitals which are fine in a report
+const ACRONYMS: [&str; 16] = ["GCSE", "GCSEs", "ICT", "PSHE", "STEM", "SEN", "SEND", "EAL", "KS3", "KS4", "KS5", "SATs", "BTEC", "RE", "PE", "DT"];
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "issue", rename_all = "snake_case")]
+pub enum Lint {
+    // a classmate's name, likely from a copied report
+    WrongName { found: String },
+    WrongPronoun { found: String, pronouns: String },
+    Placeholder,
+    RepeatedSentence,
+    DoubleSpace,
+    AllCaps,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Issue {
+    #[serde(flatten)]
+    pub lint: Lint,
+    pub span: Span,
+}
+
+// Who a report is about: the names they're called by and their pronouns, and the names of the rest of the class.
+// Pronouns aren't checked for a pupil with none recorded.
+pub struct Pupil<'a> {
+    pub names: Vec<&'a str>,
+    pub pronouns: Option<Pronouns>,
+    pub classmates: Vec<&'a str>,
+}
+
+// Every issue in a report's content, in the order they appear. Spans are char offsets like validate's.
+pub fn lint(content: &str, pupil: &Pupil) -> Vec<Issue> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut issues = vec![];
+    words(&chars, pupil, &mut issues);
+    placeholders(&chars, &mut issues);
+    repeated(&chars, &mut issues);
+    spaces(&chars, &mut issues);
+    issues.sort_by_key(|issue| issue.span);
+    issues
+}
+
+// runs of letters, digits and apostrophes with where they are
+pub(crate) fn split_words(chars: &[char]) -> Vec<(Span, String)> {
+    let mut words = vec![];
+    let mut start = None;
+    for i in 0..=chars.len() {
+        let in_word = chars.get(i).is_some_and(|c| c.is_alphanumeric() || *c == '\'' || *c == '’');
+        match (start, in_word) {
+            (None, true) => start = Some(i),
+            (Some(from), false) => {
+                let word: String = chars[from..i].iter().collect();
+                let trimmed = word.trim_end_matches(['\'', '’']);
+                words.push((Span { start: from, end: from + trimmed.chars().count() }, trimmed.to_string()));
+                start = None;
+            },
+            _ => (),
+        }
+    }
+    words
+}
+
+fn words(chars: &[char], pupil: &Pupil, issues: &mut Vec<Issue>) {
+    let own: HashSet<String> = pupil.names.iter().map(|name| name.to_lowercase()).collect();
+    let classmates: HashSet<String> = pupil.classmates.iter().map(|name| name.to_lowercase())
+        .filter(|name| !own.contains(name)).collect();
+
+    for (span, word) in split_words(chars) {
+        let bare = word.strip_suffix("'s").or(word.strip_suffix("’s")).unwrap_or(&word);
+        let lower = bare.to_lowercase();
+        if bare.starts_with(char::is_uppercase) && classmates.contains(&lower) {
+            issues.push(Issue { lint: Lint::WrongName { found: bare.to_string() }, span });
+        } else if let Some(pronouns) = pupil.pronouns.as_ref().filter(|pronouns| GENDERED.contains(&lower.as_str()) && !pronouns.has(&lower)) {
+            issues.push(Issue { lint: Lint::WrongPronoun { found: bare.to_string(), pronouns: pronouns.to_string() }, span });
+        } else if PLACEHOLDERS.contains(&lower.as_str()) || (lower.len() >= 2 && lower.chars().all(|c| c == 'x')) {
+            issues.push(Issue { lint: Lint::Placeholder, span });
+        } else if word.chars().filter(|c| c.is_alphabetic()).count() >= 2
+            && word.chars().all(|c| !c.is_lowercase())
+            && !ACRONYMS.contains(&word.as_str()) {
+            issues.push(Issue { lint: Lint::AllCaps, span });
+        }
+    }
+}
+
+// {first_name}, [name] and <name> left unfilled
+fn placeholders(chars: &[char], issues: &mut Vec<Issue>) {
+    let mut open: Option<(usize, char)> = None;
+    for (i, c) in chars.iter().enumerate() {
+        match (open, c) {
+            (None, '{') => open = Some((i, '}')),
+            (None, '[') => open = Some((i, ']')),
+            (None, '<') => open = Some((i, '>')),
+            (Some((start, close)), c) if *c == close => {
+                issues.push(Issue { lint: Lint::Placeholder, span: Span { start, end: i + 1 } });
+                open = None;
+            },
+            (Some(_), '\n') => open = None,
+            _ => (),
+        }
+    }
+}
+
+// a sentence which has been written before, ignoring case and spacing
+fn repeated(chars: &[char], issues: &mut Vec<Issue>) {
+    let mut seen = HashSet::new();
+    let mut start = 0;
+    for i in 0..=chars.len() {
+        let end = match chars.get(i) {
+            Some('.' | '!' | '?' | '\n') => i + 1,
+            None => i,
+            Some(_) => continue,
+        };
+        let sentence: String = chars[start..end].iter().collect();
+        let key = sentence.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase();
+        let key = key.trim_end_matches(['.', '!', '?']);
+        if key.split_whitespace().count() > 1 && !seen.insert(key.to_string()) {
+            let leading = chars[start..end].iter().take_while(|c| c.is_whitespace()).count();
+            issues.push(Issue { lint: Lint::RepeatedSentence, span: Span { start: start + leading, end } });
+        }
+        start = end;
+    }
+}
+
+fn spaces(chars: &[char], issues: &mut Vec<Issue>) {
+    let mut i = 0;
+    while i < chars.len() {
+        let run = chars[i..].iter().take_while(|c| **c == ' ').count();
+        if run >= 2 { issues.push(Issue { lint: Lint::DoubleSpace, span: Span { start: i, end: i + run } }) }
+        i += run.max(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ lint, Issue, Lint, Pupil };
+    use crate::search::Span;
+
+    fn sam() -> Pupil<'static> {
+        Pupil { names: vec!["Sam", "Jones"], pronouns: Some("she/her".parse().unwrap()), classmates: vec!["Alex", "Priya", "Sam"] }
+    }
+
+    fn lints(content: &str) -> Vec<Lint> {
+        lint(content, &sam()).into_iter().map(|issue| issue.lint).collect()
+    }
+
+    #[test]
+    fn clean_report() {
+        assert!(lints("Sam has worked hard this term. Her GCSE coursework is excellent and she should be proud of herself.").is_empty());
+    }
+
+    #[test]
+    fn names_from_classmates() {
+        assert_eq!(lint("Priya's essays are thoughtful.", &sam()), vec![
+            Issue { lint: Lint::WrongName { found: "Priya".to_string() }, span: Span { start: 0, end: 7 } },
+        ]);
+        // a classmate with the same name as the pupil is fine
+        assert!(lints("Sam did well.").is_empty());
+    }
+
+    #[test]
+    fn pronouns_which_are_not_the_pupils() {
+        assert_eq!(lints("Sam did well and he should be proud of himself."), vec![
+            Lint::WrongPronoun { found: "he".to_string(), pronouns: "she/her".to_string() },
+            Lint::WrongPronoun { found: "himself".to_string(), pronouns: "she/her".to_string() },
+        ]);
+        let unrecorded = Pupil { pronouns: None, ..sam() };
+        assert!(lint("She did well and he should be proud of her.", &unrecorded).is_empty());
+    }
+
+    #[test]
+    fn placeholders_left_in() {
+        assert_eq!(lints("{first_name} did well in XXX. TODO"), vec![Lint::Placeholder, Lint::Placeholder, Lint::Placeholder]);
+        assert_eq!(lint("See [name].", &sam())[0].span, Span { start: 4, end: 10 });
+    }
+
+    #[test]
+    fn repeated_sentences() {
+        let issues = lint("Sam works hard. She is kind.  sam works   hard.", &sam());
+        assert_eq!(issues.iter().map(|issue| &issue.lint).collect::<Vec<&Lint>>(), vec![&Lint::DoubleSpace, &Lint::RepeatedSentence, &Lint::DoubleSpace]);
+        assert_eq!(issues[1].span, Span { start: 30, end: 47 });
+    }
+
+    #[test]
+    fn shouting() {
+        assert_eq!(lints("Sam is VERY good at PE."), vec![Lint::AllCaps]);
+    }
+}
@@ -4,6 +4,7 @@ use tokio::{
 };
 use backend::{
     parse_connection::Connection,
+    sql::DB,
     HttpResponse,
 };
 
@@ -12,6 +13,8 @@ use backend::{
 async fn main() -> Result<()> {
     let ip = "127.0.0.1:9000";
 
+    DB::new().await.expect("Connecting to DB").migrate().await.expect("Migrating DB");
+
     let listener = TcpListener::bind(ip).await.expect("Listener Failed to Bind"); 
     println!("Connected to: {}", ip);
 
@@ -34,17 +37,24 @@ async fn main() -> Result<()> {
 async fn handle_connection(stream: TcpStream) {
 
     println!("Got Task! Executing...");
-    let request = Connection::new(stream).await.read_connection().await.expect("Unwrapping connection in main")
-        .build_request().await.expect("Unwrapping request in main");
-
-    let response = HttpResponse::build(request).await;
-
-    match response {
-        Ok((response, stream)) => { response.write(stream).await.expect("COULD NOT WRITE TO STREAM"); return println!("OK") },
-        Err(err) => 
-            // Handle if the the request was bad, match on the error, 
-            // return appropriate response
-            return println!("{:?}", err),
+    let mut connection = Connection::new(stream).await;
+    let request = match connection.read_connection().await {
+        Ok(connection) => connection.build_request().await,
+        Err(err) => Err(err),
+    };
+
+    let (response, stream) = match request {
+        Ok(request) => HttpResponse::build(request).await,
+        Err(err) => {
+            println!("Could not read request: {:?}", err);
+            let stream = if let Some(stream) = connection.into_stream() { stream } else { return };
+            (HttpResponse::unreadable(&err), stream)
+        },
+    };
+
+    // the client may have gone, or a download's rows stopped coming from the database part way
+    match response.write(stream).await {
+        Ok(()) => println!("OK"),
+        Err(err) => println!("Could not finish response: {}", err),
     }
-
 }
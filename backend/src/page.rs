@@ -0,0 +1,118 @@
+// rows sent when the client doesn't ask for a number, and the most it can ask for
+pub const PAGE_SIZE: usize = 50;
+pub const MAX_PAGE: usize = 200;
+
+// separates the parts of a sort key inside a cursor, it can't appear in a name
+const SEPARATOR: char = '\u{1f}';
+
+// Where a page of a list starts and how long it is. The cursor holds the sort key of the last row
+// already sent rather than an offset, so pages don't shift when rows are added or removed in between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    after: Option<Vec<String>>,
+    limit: usize,
+}
+
+impl Default for Page {
+    fn default() -> Page {
+        Page { after: None, limit: PAGE_SIZE }
+    }
+}
+
+impl Page {
+    // None if the cursor wasn't one we handed out
+    pub fn new(after: Option<&str>, limit: Option<usize>) -> Option<Page> {
+        let after = match after {
+            Some(cursor) => Some(decode(cursor)?),
+            None => None,
+        };
+        let limit = limit.map_or(PAGE_SIZE, |limit| limit.clamp(1, MAX_PAGE));
+        Some(Page { after, limit })
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    // the last row's id, for lists sorted by id alone: 0 on the first page, None when the cursor's key isn't an id
+    pub fn after_id(&self) -> Option<usize> {
+        match self.after.as_deref() {
+            None => Some(0),
+            Some([id]) => id.parse().ok(),
+            Some(_) => None,
+        }
+    }
+
+    // the part of the last row's sort key at i, None on the first page
+    pub fn after(&self, i: usize) -> Option<&str> {
+        self.after.as_ref()?.get(i).map(String::as_str)
+    }
+
+    // Queries fetch one row more than the page so we know if another follows.
+    // Cuts rows down to the page and gives the cursor for the next one, if any.
+    pub fn split<T>(&self, mut rows: Vec<T>, key: impl Fn(&T) -> Vec<String>) -> (Vec<T>, Option<String>) {
+        if rows.len() <= self.limit { return (rows, None) }
+        rows.truncate(self.limit);
+        let next = rows.last().map(|row| encode(&key(row)));
+        (rows, next)
+    }
+}
+
+// hex so the cursor goes in a query string as it is
+fn encode(key: &[String]) -> String {
+    key.join(&SEPARATOR.to_string()).bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode(cursor: &str) -> Option<Vec<String>> {
+    if cursor.is_empty() || !cursor.len().is_multiple_of(2) { return None }
+    let bytes = (0..cursor.len()).step_by(2)
+        .map(|i| cursor.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<Vec<u8>>>()?;
+    let key = String::from_utf8(bytes).ok()?;
+    Some(key.split(SEPARATOR).map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ decode, encode, Page, MAX_PAGE, PAGE_SIZE };
+
+    #[test]
+    fn cursors_round_trip() {
+        let key = vec!["O'Brien".to_string(), "Zoë".to_string(), "42".to_string()];
+        assert_eq!(decode(&encode(&key)), Some(key));
+        assert_eq!(decode("zz"), None);
+        assert_eq!(decode("abc"), None);
+        assert_eq!(decode("ff"), None);
+        assert_eq!(decode("é1"), None);
+        assert_eq!(Page::new(Some("nope"), None), None);
+    }
+
+    #[test]
+    fn limits_are_clamped() {
+        assert_eq!(Page::new(None, None).unwrap().limit(), PAGE_SIZE);
+        assert_eq!(Page::new(None, Some(0)).unwrap().limit(), 1);
+        assert_eq!(Page::new(None, Some(10_000)).unwrap().limit(), MAX_PAGE);
+    }
+
+    #[test]
+    fn split_gives_next_cursor_only_when_more_rows() {
+        let page = Page::new(None, Some(2)).unwrap();
+        let (rows, next) = page.split(vec![1, 2, 3], |row| vec![row.to_string()]);
+        assert_eq!(rows, vec![1, 2]);
+        let next = Page::new(next.as_deref(), Some(2)).unwrap();
+        assert_eq!(next.after(0), Some("2"));
+
+        let (rows, next) = page.split(vec![1, 2], |row| vec![row.to_string()]);
+        assert_eq!((rows, next), (vec![1, 2], None));
+    }
+
+    #[test]
+    fn id_cursors() {
+        assert_eq!(Page::default().after_id(), Some(0));
+        assert_eq!(Page::new(Some(&encode(&["42".to_string()])), None).unwrap().after_id(), Some(42));
+        // a cursor from the pupil list, surname, first name and id, or one that's been tampered with
+        let pupils = encode(&["Jones".to_string(), "Sam".to_string(), "42".to_string()]);
+        assert_eq!(Page::new(Some(&pupils), None).unwrap().after_id(), None);
+        assert_eq!(Page::new(Some(&encode(&["Jones".to_string()])), None).unwrap().after_id(), None);
+    }
+}
@@ -1,15 +1,31 @@
 use tokio::net::TcpStream;
 use tokio::io::AsyncReadExt;
 use bytes::BytesMut;
-use tokio::time::{Duration, sleep};
+use tokio::time::{Duration, Instant, timeout};
 use crate::HttpRequest;
 
 
 pub struct Connection {
     stream: Option<TcpStream>, 
     buf: Option<BytesMut>,
+    timeouts: Timeouts,
 }
 
+// How long a client can go without sending anything, and how long a whole request can take. The upload routes
+// get long enough for their largest body over a slow school connection, 64 MiB at about 600 kbit/s.
+#[derive(Debug, Clone, Copy)]
+struct Timeouts {
+    idle: Duration,
+    request: Duration,
+    upload: Duration,
+}
+
+const TIMEOUTS: Timeouts = Timeouts {
+    idle: Duration::from_secs(5),
+    request: Duration::from_secs(5),
+    upload: Duration::from_secs(15 * 60),
+};
+
 #[derive(Debug)]
 pub enum RequestError {
     UnknownLength,
@@ -32,47 +48,57 @@ impl Connection {
     pub async fn new(stream: TcpStream) -> Self  {
         let buf = Some(BytesMut::with_capacity(4096));
         let stream = Some(stream);
-        Connection { stream, buf }
+        Connection { stream, buf, timeouts: TIMEOUTS }
     }
 
+    // Reads until check_buf has the whole request. Every read has to come within the idle timeout, and the
+    // request within its deadline, which is longer once the request line says it's an upload.
     async fn get_bytes(&mut self) -> Result<&mut Self, RequestError> {
+        let started = Instant::now();
         loop {
-            if self.buf.as_ref().unwrap().capacity() > 100000 { return Err(RequestError::PostTooLarge)  }
-            match self.stream.as_mut().unwrap().read_buf(&mut self.buf.as_mut().unwrap()).await {
-                Ok(_) => {
-                    tokio::select! {
-                        _ = sleep(Duration::new(8, 0)) => { 
-                            return Err(RequestError::Timeout) 
-                        }
-                        end = self.check_buf() => { 
-                            if end { break } 
-                            continue;
-                        }
-                    };
-                },
-                Err(_) => return Err(RequestError::ConnectionClosed)
+            let buf = self.buf.as_ref().unwrap();
+            if buf.capacity() > Connection::limit(buf) { return Err(RequestError::PostTooLarge) }
+            let deadline = if Connection::upload(buf) { self.timeouts.upload } else { self.timeouts.request };
+            let left = deadline.checked_sub(started.elapsed()).ok_or(RequestError::Timeout)?;
+            match timeout(self.timeouts.idle.min(left), self.stream.as_mut().unwrap().read_buf(self.buf.as_mut().unwrap())).await {
+                Err(_) => return Err(RequestError::Timeout),
+                Ok(Ok(0)) | Ok(Err(_)) => return Err(RequestError::ConnectionClosed),
+                Ok(Ok(_)) => if self.check_buf().await { break },
             }
         }
         Ok(self)
     }
 
     pub async fn read_connection(&mut self) -> Result<&mut Self, RequestError>  {
-        tokio::select!{
-            // Content-Length header == buf.len()
-            _ = sleep(Duration::new(5, 0)) => {
-                return Err(RequestError::Timeout)
-            }
-            read = self.get_bytes() => {
-                return read
-            }
-        }
+        self.get_bytes().await
     }
 
+    // done once the headers and Content-Length bytes of body have arrived
     async fn check_buf(&self) -> bool {
-        if self.buf.as_ref().unwrap().len() > 3 && &self.buf.as_ref().unwrap()[self.buf.as_ref().unwrap().len()-4..] == b"\r\n\r\n" { 
-            return true
-        }
-        false
+        let buf = self.buf.as_ref().unwrap();
+        let end = if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") { end + 4 }
+            else { return false };
+        buf.len() >= end + Connection::content_length(&buf[..end])
+    }
+
+    // Uploads of a whole school, restoring a backup or importing pupils, can be far larger than anything else
+    fn limit(buf: &[u8]) -> usize {
+        if Connection::upload(buf) { 64 * 1024 * 1024 } else { 100000 }
+    }
+
+    fn upload(buf: &[u8]) -> bool {
+        let line = buf.split(|byte| *byte == b'\n').next().unwrap_or_default();
+        line.starts_with(b"POST ") && ["+restore ", "+import ", "+import+commit "].iter()
+            .any(|route| line.windows(route.len()).any(|window| window == route.as_bytes()))
+    }
+
+    fn content_length(headers: &[u8]) -> usize {
+        let headers = if let Ok(headers) = std::str::from_utf8(headers) { headers }
+            else { return 0 };
+        headers.split("\r\n")
+            .find_map(|header| header.split_once(':').filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length")))
+            .and_then(|(_, len)| len.trim().parse().ok())
+            .unwrap_or(0)
     }
 
     pub async fn build_request(&mut self) -> Result<HttpRequest, RequestError> {
@@ -80,17 +106,16 @@ impl Connection {
         let buf = self.buf.take().unwrap();
         let (request, body) = match std::str::from_utf8(&buf) {
             Ok(split) => {
-                let mut split = split.split("\r\n\r\n").into_iter();
+                let mut split = split.splitn(2, "\r\n\r\n");
                 let request = if let Some(body) = split.next() { body }
                     else { return Err(RequestError::NoRequest) };
-                let body = if let Some(body) = split.next() { body }
-                    else { "" };
+                let body = split.next().unwrap_or_default();
                 (request, body)
             },
             Err(_) => return Err(RequestError::NoRequest)
         };
 
-        let mut request_iter = request.split("\r\n").into_iter();
+        let mut request_iter = request.split("\r\n");
 
         // Get request line (status line) and split into components
         let request_line: Vec<&str> = if let Some(request_line) = request_iter.next() { 
@@ -111,16 +136,11 @@ impl Connection {
         // Map on the rest of the request to get the headers 
         let mut content_len = 0;
         let headers: Vec<String> = request_iter.map(|header| { 
-            let mut split = header.split(' ').into_iter();
+            let mut split = header.split(' ');
             if let Some(header) = split.next() {
-                if header == "Content-Length:" {
-                    match split.next() {
-                        Some(num) => {
-                            if let Ok(num) = num.parse() {
-                                content_len = num;
-                            }
-                        }
-                        None => ()
+                if header.eq_ignore_ascii_case("Content-Length:") {
+                    if let Some(Ok(num)) = split.next().map(str::parse) {
+                        content_len = num;
                     }
                 }
             }
@@ -139,23 +159,18 @@ impl Connection {
         Ok(HttpRequest {method , uri, version, headers, body, stream })
     }
 
-    pub async fn if_post(&mut self, body: &str, content_len: usize) -> Result<String, RequestError> {
-    let body:String = 
-        if body.len() > content_len {
-            self.buf = Some(BytesMut::with_capacity(4096));
-            match self.get_bytes().await {
-                Ok(more) => {
-                    body.to_string().push_str( 
-                        if let Ok(string) = std::str::from_utf8(&more.buf.take().unwrap()) { string }
-                            else { return Err(RequestError::CouldNotParseToString) }
-                    );
-                    body.to_string()
-                },
-                Err(_) => return Err(RequestError::ConnectionClosed) 
-            }
-       } else { body.to_string() };
+    // the stream back when no request could be built from it, to say so
+    pub fn into_stream(self) -> Option<TcpStream> {
+        self.stream
+    }
 
-        Ok(body)
+    // check_buf has already waited for the whole body, anything past Content-Length is dropped
+    pub async fn if_post(&mut self, body: &str, content_len: usize) -> Result<String, RequestError> {
+        match body.get(..content_len) {
+            Some(body) => Ok(body.to_string()),
+            None if body.len() < content_len => Err(RequestError::ConnectionClosed),
+            None => Err(RequestError::CouldNotParseToString),
+        }
     }
 
 
@@ -163,8 +178,48 @@ impl Connection {
 
 #[cfg(test)]
 mod tests {
-    use tokio::net::TcpListener;
-    use super::Connection;
+    use tokio::{ io::AsyncWriteExt, net::{ TcpListener, TcpStream }, time::{ sleep, Duration } };
+    use super::{ Connection, RequestError, Timeouts };
+
+    #[test]
+    fn uploads_can_be_larger() {
+        assert!(Connection::limit(b"POST /Hill/admin/admin+restore HTTP/1.1\r\n") > Connection::limit(b"POST /Hill/admin/report HTTP/1.1\r\n"));
+        assert_eq!(Connection::limit(b"GET /Hill/admin/admin+restore HTTP/1.1\r\n"), 100000);
+    }
+
+    // the request sent in pieces with a gap between each, read with timeouts in milliseconds rather than seconds
+    async fn slowly(uri: &str, pieces: usize, gap: u64) -> Result<usize, RequestError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("BIND");
+        let address = listener.local_addr().expect("ADDRESS");
+        let body = "x".repeat(pieces * 1000);
+        let request = format!("POST {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", uri, body.len(), body);
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(address).await.expect("CONNECT");
+            for piece in request.as_bytes().chunks(1000) {
+                stream.write_all(piece).await.expect("WRITE");
+                sleep(Duration::from_millis(gap)).await;
+            }
+            stream
+        });
+        let (stream, _) = listener.accept().await.expect("ACCEPT");
+        let timeouts = Timeouts { idle: Duration::from_millis(200), request: Duration::from_millis(300), upload: Duration::from_secs(5) };
+        let mut connection = Connection { timeouts, ..Connection::new(stream).await };
+        let read = match connection.read_connection().await {
+            Ok(connection) => connection.build_request().await.map(|request| request.body.len()),
+            Err(err) => Err(err),
+        };
+        client.abort();
+        read
+    }
+
+    #[tokio::test]
+    async fn slow_uploads_are_not_cut_off() {
+        // well past the deadline for other requests, but never idle for long
+        assert_eq!(slowly("/Hill/head/admin+restore", 20, 50).await.expect("UPLOAD"), 20 * 1000);
+        assert!(matches!(slowly("/Hill/head/report", 20, 50).await, Err(RequestError::Timeout)));
+        // an upload which stops coming is still given up on
+        assert!(matches!(slowly("/Hill/head/admin+restore", 3, 400).await, Err(RequestError::Timeout)));
+    }
 
     #[ignore]
     #[tokio::test]
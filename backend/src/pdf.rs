@@ -0,0 +1,245 @@
+// Just enough PDF to print report cards: pages of text in Helvetica and lines, written uncompressed.
+// Helvetica is one of the fonts every PDF reader has, so nothing is embedded. Its encoding only covers
+// WinAnsi (Latin-1 and a few more), so text is checked with unprintable before it's drawn: anything else would print as ?.
+
+// sizes of paper in points, 72 to an inch
+pub const A4: (f32, f32) = (595.0, 842.0);
+pub const LETTER: (f32, f32) = (612.0, 792.0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    Regular,
+    Bold,
+}
+
+// widths of ' ' to '~' in thousandths of the font size, from the fonts' metrics
+const HELVETICA: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+const HELVETICA_BOLD: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+impl Font {
+    fn name(&self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+        }
+    }
+}
+
+// A document being written, one content stream per page. Drawing goes on the last page unless another's been turned to.
+#[derive(Debug)]
+pub struct Document {
+    width: f32,
+    height: f32,
+    pages: Vec<String>,
+    current: usize,
+}
+
+// how wide the text is in points, guessing at an average letter for anything past ~
+pub fn width(text: &str, font: Font, size: f32) -> f32 {
+    let widths = match font {
+        Font::Regular => &HELVETICA,
+        Font::Bold => &HELVETICA_BOLD,
+    };
+    let thousandths: u32 = text.chars().map(|c| match c {
+        ' '..='~' => widths[c as usize - 32] as u32,
+        _ => 556,
+    }).sum();
+    thousandths as f32 * size / 1000.0
+}
+
+// The byte WinAnsiEncoding has for a char, None if it hasn't one
+fn encode(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' | '\u{a0}'..='\u{ff}' => Some(c as u8),
+        '€' => Some(0x80),
+        '‚' => Some(0x82),
+        'ƒ' => Some(0x83),
+        '„' => Some(0x84),
+        '…' => Some(0x85),
+        '†' => Some(0x86),
+        '‡' => Some(0x87),
+        'ˆ' => Some(0x88),
+        '‰' => Some(0x89),
+        'Š' => Some(0x8a),
+        '‹' => Some(0x8b),
+        'Œ' => Some(0x8c),
+        'Ž' => Some(0x8e),
+        '‘' => Some(0x91),
+        '’' => Some(0x92),
+        '“' => Some(0x93),
+        '”' => Some(0x94),
+        '•' => Some(0x95),
+        '–' => Some(0x96),
+        '—' => Some(0x97),
+        '˜' => Some(0x98),
+        '™' => Some(0x99),
+        'š' => Some(0x9a),
+        '›' => Some(0x9b),
+        'œ' => Some(0x9c),
+        'ž' => Some(0x9e),
+        'Ÿ' => Some(0x9f),
+        _ => None,
+    }
+}
+
+// the chars in the text which can't be printed, line breaks and tabs aside as text is split at them
+pub fn unprintable(text: &str) -> Vec<char> {
+    let mut chars: Vec<char> = text.chars().filter(|c| !matches!(c, '\n' | '\r' | '\t') && encode(*c).is_none()).collect();
+    chars.sort();
+    chars.dedup();
+    chars
+}
+
+// a string literal, with the bytes which would end it escaped
+fn literal(text: &str) -> Vec<u8> {
+    let mut bytes = vec![b'('];
+    for byte in text.chars().map(|c| encode(c).unwrap_or(b'?')) {
+        if matches!(byte, b'(' | b')' | b'\\') { bytes.push(b'\\') }
+        bytes.push(byte);
+    }
+    bytes.push(b')');
+    bytes
+}
+
+impl Document {
+    pub fn new((width, height): (f32, f32)) -> Document {
+        Document { width, height, pages: vec![], current: 0 }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    // everything drawn from here on goes on a new page
+    pub fn page(&mut self) {
+        self.pages.push(String::new());
+        self.current = self.pages.len() - 1;
+    }
+
+    // go back to draw on a page already started, counting from 0
+    pub fn turn(&mut self, page: usize) {
+        self.current = page.min(self.pages.len().saturating_sub(1));
+    }
+
+    fn current(&mut self) -> &mut String {
+        if self.pages.is_empty() { self.page() }
+        &mut self.pages[self.current]
+    }
+
+    // text with its baseline starting at x, y from the bottom left of the page
+    pub fn text(&mut self, x: f32, y: f32, font: Font, size: f32, text: &str) {
+        // content streams are kept as strings, so the encoded bytes are held as the Latin-1 chars with the same values
+        let literal: String = literal(text).into_iter().map(char::from).collect();
+        let content = format!("BT /{} {:.1} Tf {:.2} {:.2} Td {} Tj ET\n", font.name(), size, x, y, literal);
+        self.current().push_str(&content);
+    }
+
+    pub fn line(&mut self, from: (f32, f32), to: (f32, f32), thickness: f32) {
+        let content = format!("{:.2} w {:.2} {:.2} m {:.2} {:.2} l S\n", thickness, from.0, from.1, to.0, to.1);
+        self.current().push_str(&content);
+    }
+
+    // The finished file: catalog, page tree and fonts, then each page's content and page object, then the cross-reference table
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.pages.is_empty() { self.page() }
+        let fonts = "<< /F1 3 0 R /F2 4 0 R >>";
+        let kids: Vec<String> = (0..self.pages.len()).map(|i| format!("{} 0 R", 6 + 2 * i)).collect();
+
+        let mut objects: Vec<Vec<u8>> = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), self.pages.len()).into_bytes(),
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_vec(),
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_vec(),
+        ];
+        for (i, content) in self.pages.iter().enumerate() {
+            let content: Vec<u8> = content.chars().map(|c| c as u8).collect();
+            let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+            stream.extend(content);
+            stream.extend(b"\nendstream");
+            objects.push(stream);
+            objects.push(format!("<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.0} {:.0}] /Resources << /Font {} >> /Contents {} 0 R >>",
+                self.width, self.height, fonts, 5 + 2 * i).into_bytes());
+        }
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = vec![];
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend(format!("{} 0 obj\n", i + 1).into_bytes());
+            pdf.extend(object);
+            pdf.extend(b"\nendobj\n");
+        }
+        let xref = pdf.len();
+        pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).into_bytes());
+        for offset in offsets {
+            pdf.extend(format!("{:010} 00000 n \n", offset).into_bytes());
+        }
+        pdf.extend(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref).into_bytes());
+        pdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ literal, unprintable, width, Document, Font, A4 };
+
+    #[test]
+    fn text_is_encoded() {
+        assert_eq!(literal("Sam (Year 9) \\ Zoë’s"), b"(Sam \\(Year 9\\) \\\\ Zo\xeb\x92s)".to_vec());
+        assert_eq!(literal("Tomáš Žofia Œuvre™"), b"(Tom\xe1\x9a \x8eofia \x8cuvre\x99)".to_vec());
+        assert_eq!(literal("日本"), b"(??)".to_vec());
+    }
+
+    #[test]
+    fn all_of_winansi_is_printable() {
+        assert!(unprintable("Tomáš, Žofia, Œdipe, Ÿvette: ‚„†‡‰‹›ƒˆ˜ œ ž").is_empty());
+        assert_eq!(unprintable("Łukasz 日"), vec!['Ł', '日']);
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(width("Hi", Font::Regular, 10.0), 9.44);
+        assert!(width("Hi", Font::Bold, 10.0) > width("Hi", Font::Regular, 10.0));
+    }
+
+    #[test]
+    fn cross_references_point_at_objects() {
+        let mut document = Document::new(A4);
+        document.text(50.0, 800.0, Font::Bold, 16.0, "Report");
+        document.page();
+        document.line((50.0, 50.0), (545.0, 50.0), 0.5);
+        let pdf = document.finish();
+
+        assert!(pdf.starts_with(b"%PDF-1.4\n") && pdf.ends_with(b"%%EOF\n"));
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.contains("/Count 2"));
+        let xref = text.rfind("\nxref\n").unwrap() + 1;
+        let offsets: Vec<usize> = text[xref..].lines().skip(3).take_while(|line| line.ends_with(" n ")).map(|line| line[..10].parse().unwrap()).collect();
+        assert_eq!(offsets.len(), 8);
+        for (i, offset) in offsets.into_iter().enumerate() {
+            assert!(text[offset..].starts_with(&format!("{} 0 obj", i + 1)));
+        }
+    }
+}
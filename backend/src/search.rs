@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+// chars of report either side of a match in a snippet, and how many snippets a report gets
+const CONTEXT: usize = 40;
+const MAX_SNIPPETS: usize = 3;
+// more than this and the query is refused rather than run with a where clause per term
+pub const MAX_TERMS: usize = 10;
+
+// A run of chars in some text, start inclusive and end exclusive, counted in chars like validate's offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+// A piece of a report around what was searched for, with where the matches are in it
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Snippet {
+    pub text: String,
+    pub highlights: Vec<Span>,
+}
+
+// Splits a search into what every report has to contain: words, or "quoted phrases" kept whole
+pub fn terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = vec![];
+    for (i, part) in query.split('"').enumerate() {
+        let part = part.split_whitespace();
+        let found: Vec<String> = if i % 2 == 1 { vec![part.collect::<Vec<&str>>().join(" ")] }
+            else { part.map(str::to_string).collect() };
+        for term in found {
+            if !term.is_empty() && !terms.iter().any(|seen| seen.to_lowercase() == term.to_lowercase()) { terms.push(term) }
+        }
+    }
+    terms
+}
+
+// a term as a like pattern matching it anywhere, with its own % and _ taken literally
+pub fn contains(term: &str) -> String {
+    format!("%{}%", escape(term))
+}
+
+pub fn escape(term: &str) -> String {
+    term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// Up to MAX_SNIPPETS pieces of the content around matches of any term, ignoring case and accents as MySQL does.
+// Matches close together share a snippet, and snippets are cut at spaces with … where text was left out.
+pub fn snippets(content: &str, terms: &[String]) -> Vec<Snippet> {
+    let chars: Vec<char> = content.chars().map(|c| if c.is_whitespace() { ' ' } else { c }).collect();
+    // with where each char was, as accents written as a char of their own are left out
+    let folded: Vec<(usize, char)> = chars.iter().enumerate().filter(|(_, c)| !combining(**c)).map(|(i, c)| (i, fold(*c))).collect();
+
+    let mut matches: Vec<Span> = vec![];
+    for term in terms {
+        let term: Vec<char> = term.chars().filter(|c| !combining(*c)).map(fold).collect();
+        if term.is_empty() { continue }
+        let mut start = 0;
+        while start + term.len() <= folded.len() {
+            if folded[start..start + term.len()].iter().map(|(_, c)| *c).eq(term.iter().copied()) {
+                let mut end = folded[start + term.len() - 1].0 + 1;
+                while end < chars.len() && combining(chars[end]) { end += 1 }
+                matches.push(Span { start: folded[start].0, end });
+                start += term.len();
+            } else { start += 1 }
+        }
+    }
+    matches.sort();
+    let matches = merge(matches);
+
+    // the windows of context around each match, merged where they touch
+    let mut windows: Vec<(Span, Vec<Span>)> = vec![];
+    for found in matches {
+        let window = Span { start: found.start.saturating_sub(CONTEXT), end: (found.end + CONTEXT).min(chars.len()) };
+        match windows.last_mut() {
+            Some((last, inside)) if window.start <= last.end => {
+                last.end = window.end;
+                inside.push(found);
+            },
+            _ => windows.push((window, vec![found])),
+        }
+    }
+
+    windows.into_iter().take(MAX_SNIPPETS).map(|(window, inside)| {
+        let first = inside.first().map_or(window.start, |found| found.start);
+        let last = inside.last().map_or(window.end, |found| found.end);
+        // don't start or end part way through a word
+        let start = if window.start == 0 { 0 }
+            else { (window.start..first).find(|i| chars[*i - 1] == ' ').unwrap_or(window.start) };
+        let end = if window.end == chars.len() { window.end }
+            else { (last..=window.end).rev().find(|i| chars[*i] == ' ').unwrap_or(window.end) };
+
+        let before = if start > 0 { "…" } else { "" };
+        let after = if end < chars.len() { "…" } else { "" };
+        let shift = before.chars().count();
+        Snippet {
+            text: format!("{}{}{}", before, chars[start..end].iter().collect::<String>(), after),
+            highlights: inside.iter().map(|found| Span { start: found.start - start + shift, end: found.end - start + shift }).collect(),
+        }
+    }).collect()
+}
+
+// lowercases one char to one char, taking the accent off a Latin letter, so offsets in the folded text are offsets in the original
+fn fold(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    let c = match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => return c,
+    };
+    match c {
+        'à'..='å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+        'ĥ' | 'ħ' => 'h',
+        'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => 'i',
+        'ĵ' => 'j',
+        'ķ' => 'k',
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => 'l',
+        'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+        'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+        'ŕ' | 'ŗ' | 'ř' => 'r',
+        'ś' | 'ŝ' | 'ş' | 'š' => 's',
+        'ţ' | 'ť' | 'ŧ' => 't',
+        'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+        'ŵ' => 'w',
+        'ý' | 'ÿ' | 'ŷ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        c => c,
+    }
+}
+
+// an accent written after its letter rather than as part of it
+fn combining(c: char) -> bool {
+    ('\u{300}'..='\u{36f}').contains(&c)
+}
+
+// sorted spans joined where they overlap
+fn merge(spans: Vec<Span>) -> Vec<Span> {
+    let mut merged: Vec<Span> = vec![];
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if span.start <= last.end => last.end = last.end.max(span.end),
+            _ => merged.push(span),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ contains, snippets, terms, Snippet, Span };
+
+    #[test]
+    fn terms_keep_quoted_phrases() {
+        assert_eq!(terms(r#"homework "must  try harder" Homework"#), vec!["homework", "must try harder"]);
+        assert_eq!(terms(r#""unclosed phrase"#), vec!["unclosed phrase"]);
+        assert!(terms(r#"  "" "#).is_empty());
+    }
+
+    #[test]
+    fn like_patterns_are_escaped() {
+        assert_eq!(contains("100%_done"), r"%100\%\_done%");
+    }
+
+    #[test]
+    fn short_reports_are_one_snippet() {
+        let found = snippets("Homework is always on time. More homework please!", &["homework".to_string()]);
+        assert_eq!(found, vec![Snippet {
+            text: "Homework is always on time. More homework please!".to_string(),
+            highlights: vec![Span { start: 0, end: 8 }, Span { start: 33, end: 41 }],
+        }]);
+    }
+
+    #[test]
+    fn snippets_are_cut_at_words() {
+        let content = format!("{} must try harder {}", "word ".repeat(20), "word ".repeat(20));
+        let found = snippets(&content, &["Try Harder".to_string()]);
+        assert_eq!(found.len(), 1);
+        let Snippet { text, highlights } = &found[0];
+        assert!(text.starts_with("…word") && text.ends_with("word…"));
+        let highlighted: String = text.chars().skip(highlights[0].start).take(highlights[0].end - highlights[0].start).collect();
+        assert_eq!(highlighted, "try harder");
+    }
+
+    #[test]
+    fn accents_are_ignored() {
+        let highlights = |content: &str, term: &str| snippets(content, &[term.to_string()]).into_iter().flat_map(|snippet| snippet.highlights).collect::<Vec<Span>>();
+        assert_eq!(highlights("Trip to the Café", "cafe"), vec![Span { start: 12, end: 16 }]);
+        assert_eq!(highlights("Trip to the cafe", "CAFÉ"), vec![Span { start: 12, end: 16 }]);
+        // e then a combining acute, which the highlight takes in
+        assert_eq!(highlights("Cafe\u{301} trip", "café"), vec![Span { start: 0, end: 5 }]);
+        assert_eq!(highlights("Zoë and Chloë", "zoe"), vec![Span { start: 0, end: 3 }]);
+    }
+
+    #[test]
+    fn no_match_no_snippets() {
+        assert!(snippets("Café", &["tea".to_string()]).is_empty());
+    }
+}
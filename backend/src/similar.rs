@@ -0,0 +1,138 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+};
+use crate::lint::split_words;
+
+// words in a shingle, the runs of text two reports are compared by
+const SHINGLE: usize = 3;
+// a signature is the lowest hash of a report's shingles under each of BANDS * ROWS hash functions.
+// Reports are only compared when all ROWS of some band agree, which finds nearly every pair
+// above about (1 / BANDS)^(1 / ROWS) = 0.55 similar and few below it.
+const BANDS: usize = 20;
+const ROWS: usize = 5;
+// below this the bands miss too many pairs for the result to be worth anything
+pub const MIN_THRESHOLD: f64 = 0.5;
+pub const THRESHOLD: f64 = 0.7;
+
+// pronouns, and the verbs agreeing with them, are made the same so "she is" and "they are" don't count as a difference
+const PRONOUNS: [(&str, &str); 12] = [
+    ("he", "they"), ("she", "they"),
+    ("him", "them"),
+    ("his", "their"), ("her", "their"), ("hers", "their"), ("theirs", "their"),
+    ("himself", "themselves"), ("herself", "themselves"), ("themself", "themselves"),
+    ("is", "are"), ("has", "have"),
+];
+
+// A report as the set of its shingles, with its MinHash signature
+#[derive(Debug)]
+pub struct Document {
+    shingles: HashSet<u64>,
+    signature: Vec<u64>,
+}
+
+// Two documents, by index, and the share of their shingles they have in common
+#[derive(Debug, PartialEq)]
+pub struct Pair {
+    pub a: usize,
+    pub b: usize,
+    pub similarity: f64,
+}
+
+impl Document {
+    // The pupil's names are taken out first, as two reports which only differ by name are the same report
+    pub fn new(content: &str, names: &[&str]) -> Document {
+        let names: HashSet<String> = names.iter().map(|name| name.to_lowercase()).collect();
+        let chars: Vec<char> = content.chars().collect();
+        let words: Vec<String> = split_words(&chars).into_iter().map(|(_, word)| {
+            let word = word.to_lowercase().replace('’', "'");
+            let bare = word.strip_suffix("'s").unwrap_or(&word);
+            if names.contains(bare) { return "{name}".to_string() }
+            PRONOUNS.iter().find(|(from, _)| *from == word).map_or(word.clone(), |(_, to)| to.to_string())
+        }).collect();
+
+        let shingles: HashSet<u64> = if words.len() < SHINGLE { words.chunks(SHINGLE).map(hash).collect() }
+            else { words.windows(SHINGLE).map(hash).collect() };
+        let signature = (0..(BANDS * ROWS) as u64).map(|seed| {
+            shingles.iter().map(|shingle| mix(shingle ^ mix(seed))).min().unwrap_or(u64::MAX)
+        }).collect();
+        Document { shingles, signature }
+    }
+
+    // Jaccard similarity of the shingles, 0 when either is empty
+    pub fn similarity(&self, other: &Document) -> f64 {
+        if self.shingles.is_empty() || other.shingles.is_empty() { return 0.0 }
+        let shared = self.shingles.intersection(&other.shingles).count();
+        shared as f64 / (self.shingles.len() + other.shingles.len() - shared) as f64
+    }
+}
+
+fn hash(words: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    words.hash(&mut hasher);
+    hasher.finish()
+}
+
+// splitmix64, cheap enough to hash every shingle a hundred times
+fn mix(x: u64) -> u64 {
+    let mut x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// Every pair of documents at least threshold similar, most similar first.
+// Candidates come from the signature bands, and are then checked against their shingles so the similarity is exact.
+pub fn pairs(documents: &[Document], threshold: f64) -> Vec<Pair> {
+    let mut candidates = HashSet::new();
+    for band in 0..BANDS {
+        let mut buckets: HashMap<&[u64], Vec<usize>> = HashMap::new();
+        for (i, document) in documents.iter().enumerate().filter(|(_, document)| !document.shingles.is_empty()) {
+            buckets.entry(&document.signature[band * ROWS..(band + 1) * ROWS]).or_default().push(i);
+        }
+        for bucket in buckets.values() {
+            for (n, a) in bucket.iter().enumerate() {
+                candidates.extend(bucket[n + 1..].iter().map(|b| (*a, *b)));
+            }
+        }
+    }
+
+    let mut pairs: Vec<Pair> = candidates.into_iter()
+        .map(|(a, b)| Pair { a, b, similarity: documents[a].similarity(&documents[b]) })
+        .filter(|pair| pair.similarity >= threshold)
+        .collect();
+    pairs.sort_by(|x, y| y.similarity.total_cmp(&x.similarity).then((x.a, x.b).cmp(&(y.a, y.b))));
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ pairs, Document, Pair };
+
+    const REPORT: &str = "Sam has worked hard in lessons this term and his homework is always handed in on time. \
+        He should now focus on checking his answers carefully before moving on to the next question.";
+
+    #[test]
+    fn names_and_pronouns_are_ignored() {
+        let sam = Document::new(REPORT, &["Sam", "Jones"]);
+        let priya = Document::new(&REPORT.replace("Sam", "Priya").replace(" his ", " her ").replace("He ", "She "), &["Priya", "Patel"]);
+        assert_eq!(sam.similarity(&priya), 1.0);
+        assert_eq!(sam.similarity(&Document::new("", &[])), 0.0);
+    }
+
+    #[test]
+    fn similar_pairs_are_found() {
+        let documents = vec![
+            Document::new(REPORT, &["Sam"]),
+            Document::new("Alex finds algebra difficult but asks good questions and has improved a great deal since September.", &["Alex"]),
+            Document::new(&REPORT.replace("Sam", "Alex").replace("carefully ", ""), &["Alex"]),
+            Document::new("", &["Jo"]),
+        ];
+        let found = pairs(&documents, 0.5);
+        assert_eq!(found.len(), 1);
+        let Pair { a, b, similarity } = found[0];
+        assert_eq!((a, b), (0, 2));
+        assert!(similarity > 0.8 && similarity < 1.0);
+        assert!(pairs(&documents, 1.0).is_empty());
+    }
+}
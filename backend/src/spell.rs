@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    sync::OnceLock,
+};
+use crate::{
+    lint::split_words,
+    search::Span,
+};
+
+// the bundled word list, British spellings, built into the binary so checking never needs the network
+const WORDS: &str = include_str!("../dict/en_GB.txt");
+// how far a suggestion can be from the word as written, and how many are given
+const MAX_EDITS: usize = 2;
+const MAX_SUGGESTIONS: usize = 5;
+// how many suffixes and prefixes can be taken off a word looking for one in the dictionary: un-help-ful-ly
+const MAX_AFFIXES: usize = 3;
+
+// endings which regular words are made with, and what the word might have ended with before: tidied -> tidy
+const SUFFIXES: [(&str, &[&str]); 22] = [
+    ("s", &[""]),
+    ("es", &[""]),
+    ("ies", &["y"]),
+    ("ed", &["", "e"]),
+    ("ied", &["y"]),
+    ("ing", &["", "e"]),
+    ("ying", &["ie"]),
+    ("ly", &["", "le"]),
+    ("ily", &["y"]),
+    ("ally", &[""]),
+    ("er", &["", "e"]),
+    ("ier", &["y"]),
+    ("est", &["", "e"]),
+    ("iest", &["y"]),
+    ("ness", &[""]),
+    ("iness", &["y"]),
+    ("ment", &[""]),
+    ("ful", &[""]),
+    ("less", &[""]),
+    ("able", &["", "e"]),
+    ("ation", &["e", ""]),
+    ("ship", &[""]),
+];
+const PREFIXES: [&str; 16] = ["un", "re", "dis", "in", "im", "ir", "il", "non", "over", "under", "mis", "pre", "co", "sub", "inter", "out"];
+
+fn dictionary() -> &'static HashSet<&'static str> {
+    static DICTIONARY: OnceLock<HashSet<&'static str>> = OnceLock::new();
+    DICTIONARY.get_or_init(|| WORDS.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).collect())
+}
+
+// text to check, which needn't be a saved report
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Check {
+    pub content: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Misspelling {
+    pub word: String,
+    pub span: Span,
+    pub suggestions: Vec<String>,
+}
+
+// The bundled dictionary along with a school's own words: names, subjects and whatever admins have added
+pub struct Checker {
+    school: HashSet<String>,
+}
+
+impl Checker {
+    pub fn new(words: impl IntoIterator<Item = String>) -> Checker {
+        // names and subjects can be more than one word: Mary Ann, Design_Technology
+        let school = words.into_iter()
+            .flat_map(|words| words.split(|c: char| c.is_whitespace() || c == '_' || c == '-').map(str::to_lowercase).collect::<Vec<String>>())
+            .filter(|word| !word.is_empty())
+            .collect();
+        Checker { school }
+    }
+
+    // Every word neither dictionary knows, with suggestions. Numbers and acronyms are left alone.
+    pub fn check(&self, content: &str) -> Vec<Misspelling> {
+        let chars: Vec<char> = content.chars().collect();
+        split_words(&chars).into_iter().filter_map(|(span, word)| {
+            let letters = word.chars().filter(|c| c.is_alphabetic()).count();
+            let acronym = letters > 1 && word.chars().all(|c| !c.is_lowercase());
+            if word.chars().any(|c| c.is_numeric()) || acronym || self.known(&word) { return None }
+            Some(Misspelling { suggestions: self.suggest(&word), word, span })
+        }).collect()
+    }
+
+    fn known(&self, word: &str) -> bool {
+        let word = word.to_lowercase().replace('’', "'");
+        let bare = word.strip_suffix("'s").unwrap_or(&word);
+        self.school.contains(bare) || known(&word, MAX_AFFIXES)
+    }
+
+    // Words one edit away, in any form known() accepts, then dictionary words two edits away.
+    // Suggestions start with a capital when the word did.
+    fn suggest(&self, word: &str) -> Vec<String> {
+        let lower = word.to_lowercase();
+        let mut suggestions: Vec<String> = edits(&lower).into_iter().filter(|edit| self.known(edit)).collect();
+        let first = lower.chars().next();
+        suggestions.sort_by_key(|suggestion| (suggestion.chars().next() != first, suggestion.clone()));
+        suggestions.dedup();
+
+        let mut further: Vec<(usize, &str)> = dictionary().iter().copied()
+            .chain(self.school.iter().map(String::as_str))
+            .filter(|candidate| candidate.len().abs_diff(lower.len()) <= MAX_EDITS)
+            .filter_map(|candidate| Some((distance(&lower, candidate, MAX_EDITS)?, candidate)))
+            .collect();
+        further.sort();
+        for (_, candidate) in further {
+            if !suggestions.iter().any(|suggestion| suggestion == candidate) { suggestions.push(candidate.to_string()) }
+        }
+
+        suggestions.truncate(MAX_SUGGESTIONS);
+        if word.starts_with(char::is_uppercase) {
+            suggestions = suggestions.iter().map(|suggestion| crate::template::capitalise(suggestion)).collect();
+        }
+        suggestions
+    }
+}
+
+// whether the word, or one it's regularly made from, is in the dictionary
+fn known(word: &str, affixes: usize) -> bool {
+    if dictionary().contains(word) { return true }
+    if affixes == 0 { return false }
+    stems(word).iter().any(|stem| stem.chars().count() >= 2 && known(stem, affixes - 1))
+}
+
+// the words this one could be made from by one regular suffix or prefix
+fn stems(word: &str) -> Vec<String> {
+    let mut stems = vec![];
+    if let Some(stem) = word.strip_suffix("'s") { stems.push(stem.to_string()) }
+    for (suffix, endings) in SUFFIXES {
+        let stem = if let Some(stem) = word.strip_suffix(suffix) { stem } else { continue };
+        for ending in endings {
+            stems.push(format!("{}{}", stem, ending));
+        }
+        // stopped -> stop, running -> run
+        let mut last = stem.chars().rev();
+        if let (Some(a), Some(b)) = (last.next(), last.next()) {
+            if a == b && !"aeiou".contains(a) { stems.push(stem[..stem.len() - a.len_utf8()].to_string()) }
+        }
+    }
+    for prefix in PREFIXES {
+        if let Some(stem) = word.strip_prefix(prefix) { stems.push(stem.trim_start_matches('-').to_string()) }
+    }
+    stems
+}
+
+// every string one deletion, swap, change or insertion from the word
+fn edits(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let letters = "abcdefghijklmnopqrstuvwxyz'";
+    let mut edits = vec![];
+    for i in 0..=chars.len() {
+        let (before, after) = chars.split_at(i);
+        let before: String = before.iter().collect();
+        if !after.is_empty() {
+            edits.push(format!("{}{}", before, after[1..].iter().collect::<String>()));
+        }
+        if after.len() > 1 {
+            edits.push(format!("{}{}{}{}", before, after[1], after[0], after[2..].iter().collect::<String>()));
+        }
+        for letter in letters.chars() {
+            if !after.is_empty() && after[0] != letter {
+                edits.push(format!("{}{}{}", before, letter, after[1..].iter().collect::<String>()));
+            }
+            edits.push(format!("{}{}{}", before, letter, after.iter().collect::<String>()));
+        }
+    }
+    edits
+}
+
+// edits between the words counting a swap of neighbours as one, None once it's more than max
+fn distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1).min(row[j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        if row.iter().all(|d| *d > max) { return None }
+        rows.push(row);
+    }
+    Some(rows[a.len()][b.len()]).filter(|d| *d <= max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ distance, Checker };
+
+    fn checker() -> Checker {
+        Checker::new(vec!["Priya".to_string(), "Mary Ann".to_string(), "Design_Technology".to_string()])
+    }
+
+    fn misspelt(content: &str) -> Vec<String> {
+        checker().check(content).into_iter().map(|misspelling| misspelling.word).collect()
+    }
+
+    #[test]
+    fn british_spellings() {
+        assert!(misspelt("Her behaviour and organisation in the centre of the programme have been excellent.").is_empty());
+        assert_eq!(misspelt("Her behavior has been excellent."), vec!["behavior"]);
+        assert!(misspelt("A chatty, articulate and supportive teammate, collaborative and enthusiastically working on pronunciation.").is_empty());
+    }
+
+    #[test]
+    fn regular_forms_are_known() {
+        assert!(misspelt("She tidied up, kept trying and worked unhelpfully but happily; the pupils' stopped running.").is_empty());
+        assert!(misspelt("Priya's and Mary Ann's work in Design Technology, GCSE and KS3 in 2024").is_empty());
+    }
+
+    #[test]
+    fn suggestions() {
+        let found = checker().check("Priya has recieved Consistant praise from Pryia");
+        assert_eq!(found.iter().map(|misspelling| misspelling.word.as_str()).collect::<Vec<&str>>(), vec!["recieved", "Consistant", "Pryia"]);
+        assert_eq!(found[0].suggestions[0], "received");
+        assert_eq!(found[1].suggestions[0], "Consistent");
+        assert_eq!(found[2].suggestions[0], "Priya");
+        assert_eq!((found[0].span.start, found[0].span.end), (10, 18));
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(distance("recieve", "receive", 2), Some(1));
+        assert_eq!(distance("kitten", "sitting", 2), None);
+        assert_eq!(distance("same", "same", 2), Some(0));
+    }
+}
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use crate::{
+    lint::split_words,
+    template,
+};
+
+// words in a row which have to match a bank entry's text before they count as taken from it,
+// so common short phrases like "has worked" aren't counted
+const SHINGLE: usize = 3;
+
+// How long and how hard to read a report is
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Stats {
+    pub words: usize,
+    pub sentences: usize,
+    // Flesch reading ease, higher is easier: 60-70 is plain English, below 30 is dense
+    pub readability: f64,
+    // the fraction of words which are comment bank text, 0 to 1
+    pub bank_share: f64,
+}
+
+// Stats across many reports, for finding a teacher, class or subject whose reports are too short or too dense
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct Summary {
+    pub reports: usize,
+    pub mean_words: f64,
+    pub min_words: usize,
+    pub max_words: usize,
+    pub mean_sentences: f64,
+    pub mean_readability: f64,
+    pub mean_bank_share: f64,
+}
+
+// The runs of words written into comment bank entries, which reports made from them will share
+#[derive(Debug, Default)]
+pub struct Bank {
+    shingles: HashSet<Vec<String>>,
+}
+
+impl Bank {
+    // Only the text around fields counts: what a field was filled with is different in every report
+    pub fn new<'a>(templates: impl IntoIterator<Item = &'a str>) -> Bank {
+        let mut shingles = HashSet::new();
+        for literal in templates.into_iter().flat_map(template::literals) {
+            let words = words(&literal);
+            shingles.extend(words.windows(SHINGLE).map(<[String]>::to_vec));
+        }
+        Bank { shingles }
+    }
+}
+
+// lowercase words with curly apostrophes made straight, so a bank entry matches however it was pasted
+fn words(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    split_words(&chars).into_iter().map(|(_, word)| word.to_lowercase().replace('’', "'")).collect()
+}
+
+// A guess at the syllables in a word from its groups of vowels, good enough for readability scores
+fn syllables(word: &str) -> usize {
+    let word = word.to_lowercase();
+    let chars: Vec<char> = word.chars().filter(|c| c.is_alphabetic()).collect();
+    let vowel = |c: &char| "aeiouy".contains(*c);
+    let mut count = chars.iter().enumerate().filter(|(i, c)| vowel(c) && (*i == 0 || !vowel(&chars[i - 1]))).count();
+    // the e of make and hope is silent, but not the le of table
+    if word.ends_with('e') && !word.ends_with("le") && count > 1 { count -= 1 }
+    count.max(1)
+}
+
+// sentences end at . ! ? or a line break, and a last one needn't be finished
+fn sentences(content: &str) -> usize {
+    content.split(['.', '!', '?', '\n']).filter(|sentence| sentence.chars().any(char::is_alphanumeric)).count()
+}
+
+fn round(value: f64, places: i32) -> f64 {
+    let scale = 10f64.powi(places);
+    (value * scale).round() / scale
+}
+
+impl Stats {
+    pub fn new(content: &str, bank: &Bank) -> Stats {
+        let words = words(content);
+        let sentences = sentences(content);
+        if words.is_empty() { return Stats { words: 0, sentences, readability: 0.0, bank_share: 0.0 } }
+
+        let syllables: usize = words.iter().map(|word| syllables(word)).sum();
+        let (count, sentences) = (words.len() as f64, sentences.max(1) as f64);
+        let readability = 206.835 - 1.015 * (count / sentences) - 84.6 * (syllables as f64 / count);
+
+        let mut from_bank = vec![false; words.len()];
+        for (i, shingle) in words.windows(SHINGLE).enumerate() {
+            if bank.shingles.contains(shingle) { from_bank[i..i + SHINGLE].fill(true) }
+        }
+        let bank_share = from_bank.iter().filter(|from_bank| **from_bank).count() as f64 / count;
+
+        Stats { words: words.len(), sentences: sentences as usize, readability: round(readability, 1), bank_share: round(bank_share, 3) }
+    }
+}
+
+impl Summary {
+    pub fn new<'a>(stats: impl IntoIterator<Item = &'a Stats>) -> Summary {
+        let stats: Vec<&Stats> = stats.into_iter().collect();
+        let mean = |value: &dyn Fn(&Stats) -> f64, places| {
+            if stats.is_empty() { 0.0 } else { round(stats.iter().map(|stats| value(stats)).sum::<f64>() / stats.len() as f64, places) }
+        };
+        Summary {
+            reports: stats.len(),
+            mean_words: mean(&|stats| stats.words as f64, 1),
+            min_words: stats.iter().map(|stats| stats.words).min().unwrap_or_default(),
+            max_words: stats.iter().map(|stats| stats.words).max().unwrap_or_default(),
+            mean_sentences: mean(&|stats| stats.sentences as f64, 1),
+            mean_readability: mean(&|stats| stats.readability, 1),
+            mean_bank_share: mean(&|stats| stats.bank_share, 3),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ sentences, syllables, Bank, Stats, Summary };
+
+    #[test]
+    fn counts() {
+        assert_eq!(sentences("Sam works hard. Is it enough? Yes!\nKeep going"), 4);
+        assert_eq!(sentences("..."), 0);
+        assert_eq!([syllables("cat"), syllables("make"), syllables("table"), syllables("pupil"), syllables("consistently")], [1, 1, 2, 2, 4]);
+    }
+
+    #[test]
+    fn readability() {
+        let plain = Stats::new("Sam has had a good term. She works hard and is kind to all.", &Bank::default());
+        let dense = Stats::new("Samantha demonstrates considerable conceptual understanding, articulating sophisticated interpretations consistently.", &Bank::default());
+        assert_eq!((plain.words, plain.sentences), (14, 2));
+        assert!(plain.readability > 80.0);
+        assert!(dense.readability < 0.0);
+    }
+
+    #[test]
+    fn bank_text() {
+        let bank = Bank::new(["{first_name} has made excellent progress this term.", "{They} {is|are} a pleasure to teach."]);
+        let stats = Stats::new("Sam has made excellent progress this term. She is a pleasure to teach in every lesson.", &bank);
+        // has made excellent progress this term, a pleasure to teach, of 16 words
+        assert_eq!(stats.bank_share, 0.625);
+        assert_eq!(Stats::new("Sam did well.", &bank).bank_share, 0.0);
+        assert_eq!(Stats::new("", &bank), Stats { words: 0, sentences: 0, readability: 0.0, bank_share: 0.0 });
+    }
+
+    #[test]
+    fn summaries() {
+        let bank = Bank::default();
+        let stats = [Stats::new("One two three.", &bank), Stats::new("One two three four five six seven.", &bank)];
+        let summary = Summary::new(&stats);
+        assert_eq!((summary.reports, summary.mean_words, summary.min_words, summary.max_words), (2, 5.0, 3, 7));
+        assert_eq!(Summary::new(&[]).reports, 0);
+    }
+}
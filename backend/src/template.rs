@@ -0,0 +1,290 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+};
+
+// Report templates are plain text with {field} placeholders, {{ and }} for literal braces.
+// {They} is capitalised, {first_name's} possessive, and {has|have} picks the verb agreeing with the pupil's pronouns.
+// Anything filled in at the start of a sentence is capitalised.
+// Offsets in errors are char offsets into the template like validate's.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum TemplateError {
+    Unclosed { at: usize },
+    Unopened { at: usize },
+    // placeholders are lowercase letters, digits and _, with a capital first letter and 's allowed
+    BadField { at: usize },
+    Missing { at: usize, field: String },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field { at: usize, name: String, capital: bool, possessive: bool },
+    // the words for one person and more than one, {is|are}
+    Choice { singular: String, plural: String },
+}
+
+// The forms of a pupil's pronouns, named after they/them/their/theirs/themselves.
+// plural is for verb agreement: they are, she is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pronouns {
+    they: String,
+    them: String,
+    their: String,
+    theirs: String,
+    themselves: String,
+    plural: bool,
+}
+
+// What to fill a template with for a pupil: a bank entry or some text, and values for any fields the pupil doesn't give
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Render {
+    pub pupil_id: usize,
+    pub subject: String,
+    pub term: String,
+    #[serde(default)]
+    pub comment: Option<usize>,
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+}
+
+// Text to fill in for one pupil, outside any subject
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Text {
+    pub template: String,
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+}
+
+impl Default for Pronouns {
+    fn default() -> Pronouns {
+        Pronouns::new(["they", "them", "their", "theirs", "themselves"], true)
+    }
+}
+
+impl Pronouns {
+    fn new(forms: [&str; 5], plural: bool) -> Pronouns {
+        let [they, them, their, theirs, themselves] = forms.map(str::to_string);
+        Pronouns { they, them, their, theirs, themselves, plural }
+    }
+
+    // whether the word is one of these pronouns, lowercase
+    pub fn has(&self, word: &str) -> bool {
+        [&self.they, &self.them, &self.their, &self.theirs, &self.themselves].iter().any(|form| *form == word)
+    }
+
+    fn get(&self, field: &str) -> Option<&str> {
+        match field {
+            "they" => Some(&self.they),
+            "them" => Some(&self.them),
+            "their" => Some(&self.their),
+            "theirs" => Some(&self.theirs),
+            "themselves" | "themself" => Some(&self.themselves),
+            _ => None,
+        }
+    }
+}
+
+// they/them, she/her and he/him, or all five forms for anything else: xe/xem/xyr/xyrs/xemself
+impl FromStr for Pronouns {
+    type Err = ();
+    fn from_str(pronouns: &str) -> Result<Pronouns, ()> {
+        let pronouns = pronouns.trim().to_lowercase();
+        let forms: Vec<&str> = pronouns.split('/').map(str::trim).collect();
+        match forms.as_slice() {
+            ["they", "them"] => Ok(Pronouns::default()),
+            ["she", "her"] => Ok(Pronouns::new(["she", "her", "her", "hers", "herself"], false)),
+            ["he", "him"] => Ok(Pronouns::new(["he", "him", "his", "his", "himself"], false)),
+            [they, them, their, theirs, themselves] if forms.iter().all(|form| !form.is_empty() && form.chars().all(char::is_alphabetic)) => {
+                Ok(Pronouns::new([*they, *them, *their, *theirs, *themselves], *they == "they"))
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+// how the pronouns are stored, the short form where there is one
+impl fmt::Display for Pronouns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.they.as_str(), self.them.as_str(), self.themselves.as_str()) {
+            ("they", "them", "themselves") | ("she", "her", "herself") | ("he", "him", "himself") => write!(f, "{}/{}", self.they, self.them),
+            _ => write!(f, "{}/{}/{}/{}/{}", self.they, self.them, self.their, self.theirs, self.themselves),
+        }
+    }
+}
+
+fn parse(template: &str) -> Result<Vec<Part>, Vec<TemplateError>> {
+    let mut parts = vec![];
+    let mut errors = vec![];
+    let mut text = String::new();
+    let mut chars = template.chars().enumerate().peekable();
+    while let Some((at, c)) = chars.next() {
+        match c {
+            '{' if chars.next_if(|(_, c)| *c == '{').is_some() => text.push('{'),
+            '}' if chars.next_if(|(_, c)| *c == '}').is_some() => text.push('}'),
+            '}' => errors.push(TemplateError::Unopened { at }),
+            '{' => {
+                let mut name = String::new();
+                let closed = loop {
+                    match chars.next() {
+                        Some((_, '}')) => break true,
+                        Some((_, c)) => name.push(c),
+                        None => break false,
+                    }
+                };
+                if !closed {
+                    errors.push(TemplateError::Unclosed { at });
+                } else if let Some((singular, plural)) = name.split_once('|') {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                    parts.push(Part::Choice { singular: singular.to_string(), plural: plural.to_string() });
+                } else if let Some(field) = field(&name, at) {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                    parts.push(field);
+                } else {
+                    errors.push(TemplateError::BadField { at });
+                }
+            },
+            c => text.push(c),
+        }
+    }
+    parts.push(Part::Text(text));
+    if errors.is_empty() { Ok(parts) } else { Err(errors) }
+}
+
+// First_name's -> first_name, capitalised and possessive
+fn field(name: &str, at: usize) -> Option<Part> {
+    let (name, possessive) = match name.strip_suffix("'s") {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    let mut chars = name.chars();
+    let capital = chars.next()?.is_ascii_uppercase();
+    let name = name.to_ascii_lowercase();
+    if !name.starts_with(|c: char| c.is_ascii_lowercase()) || !chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') { return None }
+    Some(Part::Field { at, name, capital, possessive })
+}
+
+// whether text added after what's been written so far starts a sentence
+fn sentence_start(written: &str) -> bool {
+    let trimmed = written.trim_end();
+    trimmed.is_empty() || trimmed.ends_with(['.', '!', '?']) || written[trimmed.len()..].contains('\n')
+}
+
+pub(crate) fn capitalise(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// whether a template can be saved to a bank
+pub fn check(template: &str) -> Result<(), Vec<TemplateError>> {
+    parse(template).map(|_| ())
+}
+
+// the text written around the fields, which is the same in every report made from the template
+pub fn literals(template: &str) -> Vec<String> {
+    parse(template).unwrap_or_default().into_iter().filter_map(|part| match part {
+        Part::Text(text) if !text.trim().is_empty() => Some(text),
+        _ => None,
+    }).collect()
+}
+
+// The template with every field filled in from the pronouns or values, or every field there's no value for.
+// Text between fields is left as it was written.
+pub fn render(template: &str, values: &HashMap<String, String>, pronouns: &Pronouns) -> Result<String, Vec<TemplateError>> {
+    let mut rendered = String::new();
+    let mut errors = vec![];
+    for part in parse(template)? {
+        let (value, capital) = match part {
+            Part::Text(text) => {
+                rendered.push_str(&text);
+                continue
+            },
+            Part::Choice { singular, plural } => (if pronouns.plural { plural } else { singular }, false),
+            Part::Field { at, name, capital, possessive } => {
+                let value = match pronouns.get(&name).or(values.get(&name).map(String::as_str)) {
+                    Some(value) => value.to_string(),
+                    None => {
+                        errors.push(TemplateError::Missing { at, field: name });
+                        continue
+                    },
+                };
+                (if possessive { format!("{}'s", value) } else { value }, capital)
+            },
+        };
+        if capital || sentence_start(&rendered) { rendered.push_str(&capitalise(&value)) } else { rendered.push_str(&value) }
+    }
+    if errors.is_empty() { Ok(rendered) } else { Err(errors) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ check, literals, render, Pronouns, TemplateError };
+    use std::collections::HashMap;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn fills_fields() {
+        let values = values(&[("first_name", "Sam"), ("adverb", "diligently"), ("term", "autumn")]);
+        let they = Pronouns::default();
+        assert_eq!(render("{first_name} has worked {adverb} this {term}.", &values, &they), Ok("Sam has worked diligently this autumn.".to_string()));
+        assert_eq!(render("{{not a field}} {first_name}", &values, &they), Ok("{not a field} Sam".to_string()));
+    }
+
+    #[test]
+    fn reports_missing_fields() {
+        assert_eq!(render("{first_name} is {adverb}", &values(&[("first_name", "Sam")]), &Pronouns::default()),
+            Err(vec![TemplateError::Missing { at: 16, field: "adverb".to_string() }]));
+    }
+
+    #[test]
+    fn rejects_bad_syntax() {
+        assert_eq!(check("Well done {first_name"), Err(vec![TemplateError::Unclosed { at: 10 }]));
+        assert_eq!(check("Well done} {First Name} {}"), Err(vec![
+            TemplateError::Unopened { at: 9 },
+            TemplateError::BadField { at: 11 },
+            TemplateError::BadField { at: 24 },
+        ]));
+        assert!(check("No fields at all").is_ok());
+        assert!(check("{First_name's} {is|are}").is_ok());
+        assert_eq!(check("{fIRST}"), Err(vec![TemplateError::BadField { at: 0 }]));
+    }
+
+    #[test]
+    fn literal_text() {
+        assert_eq!(literals("{They} {has|have} worked hard. {first_name} should be proud."), vec![" worked hard. ", " should be proud."]);
+        assert!(literals("{unclosed").is_empty());
+    }
+
+    #[test]
+    fn pronouns_agree() {
+        let template = "{first_name} has done well. {their} homework is always in and {they} {is|are} proud of {themselves}.";
+        let values = values(&[("first_name", "Sam")]);
+        let she: Pronouns = "she/her".parse().unwrap();
+        assert_eq!(render(template, &values, &she).unwrap(), "Sam has done well. Her homework is always in and she is proud of herself.");
+        let they: Pronouns = "They / Them".parse().unwrap();
+        assert_eq!(render(template, &values, &they).unwrap(), "Sam has done well. Their homework is always in and they are proud of themselves.");
+        let xe: Pronouns = "xe/xem/xyr/xyrs/xemself".parse().unwrap();
+        assert_eq!(render("{They} said it was {theirs}.", &values, &xe).unwrap(), "Xe said it was xyrs.");
+        assert_eq!(render("{first_name's} work.\n{them}", &values, &xe).unwrap(), "Sam's work.\nXem");
+    }
+
+    #[test]
+    fn pronouns_are_stored_short() {
+        for pronouns in ["they/them", "she/her", "he/him", "xe/xem/xyr/xyrs/xemself"] {
+            assert_eq!(pronouns.parse::<Pronouns>().unwrap().to_string(), pronouns);
+        }
+        assert!("she".parse::<Pronouns>().is_err());
+        assert!("a/b/c/d/e1".parse::<Pronouns>().is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+
+// report columns are varchar(1000), anything longer is cut off or refused by MySQL
+pub const MAX_CHARS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Limits {
+    pub max_chars: usize,
+    pub max_words: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum Invalid {
+    TooManyChars { max: usize, found: usize },
+    TooManyWords { max: usize, found: usize },
+    ControlCharacter { at: usize },
+}
+
+// An error tied to the report and field it came from so the editor can show it in place
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub pupil_id: usize,
+    pub subject: String,
+    pub term: String,
+    pub field: &'static str,
+    #[serde(flatten)]
+    pub error: Invalid,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { max_chars: MAX_CHARS, max_words: None }
+    }
+}
+
+impl Limits {
+    pub fn new(max_chars: Option<usize>, max_words: Option<usize>) -> Limits {
+        let max_chars = max_chars.map_or(MAX_CHARS, |max| max.min(MAX_CHARS));
+        Limits { max_chars, max_words }
+    }
+}
+
+// Checks report text against the school's limits, returning it with whitespace normalised.
+// Offsets in errors are char offsets into the text as it was sent.
+pub fn content(content: &str, limits: &Limits) -> Result<String, Vec<Invalid>> {
+    let mut errors: Vec<Invalid> = content.chars().enumerate().filter_map(|(at, c)| match c {
+        '\n' | '\r' | '\t' => None,
+        c if c.is_control() => Some(Invalid::ControlCharacter { at }),
+        _ => None,
+    }).collect();
+
+    let content = normalise(content);
+
+    let chars = content.chars().count();
+    if chars > limits.max_chars {
+        errors.push(Invalid::TooManyChars { max: limits.max_chars, found: chars });
+    }
+    if let Some(max) = limits.max_words {
+        let words = content.split_whitespace().count();
+        if words > max { errors.push(Invalid::TooManyWords { max, found: words }) }
+    }
+
+    if errors.is_empty() { Ok(content) } else { Err(errors) }
+}
+
+// Collapses runs of spaces within lines, trims lines and keeps at most one blank line between paragraphs
+pub fn normalise(content: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    for line in content.replace("\r\n", "\n").replace('\r', "\n").split('\n') {
+        let line = line.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) { continue }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) { lines.pop(); }
+    lines.join("\n")
+}
+
+// A calendar date written the way MySQL takes it, YYYY-MM-DD
+pub fn date(date: &str) -> bool {
+    let mut parts = date.split('-');
+    let (year, month, day) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(year), Some(month), Some(day), None) if year.len() == 4 && month.len() == 2 && day.len() == 2 => (year, month, day),
+        _ => return false,
+    };
+    let (year, month, day): (u16, u8, u8) = match (year.parse(), month.parse(), day.parse()) {
+        (Ok(year), Ok(month), Ok(day)) => (year, month, day),
+        _ => return false,
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return false,
+    };
+    (1..=days).contains(&day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ content, date, normalise, Invalid, Limits, MAX_CHARS };
+
+    #[test]
+    fn dates() {
+        assert!(date("2024-02-29"));
+        assert!(date("2023-12-31"));
+        assert!(!date("2023-02-29"));
+        assert!(!date("1900-02-29"));
+        assert!(!date("2023-04-31"));
+        assert!(!date("2023-13-01"));
+        assert!(!date("2023-1-01"));
+        assert!(!date("01/02/2023"));
+        assert!(!date("2023-01-01-01"));
+    }
+
+    #[test]
+    fn normalises_whitespace() {
+        assert_eq!(normalise("  Jo  has\tworked   hard. \r\n\r\n\r\n\nWell done!  \n\n"), "Jo has worked hard.\n\nWell done!");
+        assert_eq!(normalise("\n\nA\u{a0} B\n"), "A B");
+        assert_eq!(normalise(""), "");
+    }
+
+    #[test]
+    fn rejects_control_characters() {
+        let errors = content("Good\u{7}work\u{0}", &Limits::default()).unwrap_err();
+        assert_eq!(errors, vec![Invalid::ControlCharacter { at: 4 }, Invalid::ControlCharacter { at: 9 }]);
+        assert!(content("Line one\nLine\ttwo", &Limits::default()).is_ok());
+    }
+
+    // a body which isn't UTF-8 is refused before it gets here, a replacement character that was sent is only text
+    #[test]
+    fn replacement_characters_are_text() {
+        assert_eq!(content("Caf\u{FFFD}", &Limits::default()), Ok("Caf\u{FFFD}".to_string()));
+    }
+
+    #[test]
+    fn limits_count_normalised_text() {
+        let limits = Limits::new(Some(10), Some(2));
+        assert_eq!(content("  one    two  ", &limits), Ok("one two".to_string()));
+        assert_eq!(content("one two three", &limits).unwrap_err(), vec![
+            Invalid::TooManyChars { max: 10, found: 13 },
+            Invalid::TooManyWords { max: 2, found: 3 },
+        ]);
+    }
+
+    #[test]
+    fn chars_are_capped_by_column() {
+        assert_eq!(Limits::new(Some(5000), None).max_chars, MAX_CHARS);
+        assert_eq!(Limits::new(None, None).max_chars, MAX_CHARS);
+    }
+}
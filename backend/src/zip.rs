@@ -0,0 +1,205 @@
+// ZIP archives with every file stored as it is. PDFs and spreadsheets are compressed already, so there's little to gain
+// from deflating them, and stored files are read by everything. zip makes an archive at once, Stream a piece at a time.
+
+const LOCAL: u32 = 0x04034b50;
+const CENTRAL: u32 = 0x02014b50;
+const END: u32 = 0x06054b50;
+// version 2.0 of the format, with names in utf-8
+const VERSION: u16 = 20;
+const UTF8_NAMES: u16 = 0x0800;
+// the crc and sizes follow a file rather than being in its header, for when they aren't known until it's written
+const DESCRIPTOR: u16 = 0x0008;
+const DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+// DOS time and date, midnight on 1 January 1980: the files get no time of their own
+const TIME: u16 = 0;
+const DATE: u16 = (1 << 5) | 1;
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !update(!0, bytes)
+}
+
+// the crc so far, before it's inverted, carried on over more bytes
+fn update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+// the fields after the signature which a file's header and its central directory entry share
+fn shared(flags: u16, crc: u32, size: u32, name: &[u8]) -> Vec<u8> {
+    let mut shared = vec![];
+    for field in [VERSION, flags, 0, TIME, DATE] { shared.extend(field.to_le_bytes()) }
+    for field in [crc, size, size] { shared.extend(field.to_le_bytes()) }
+    shared.extend((name.len() as u16).to_le_bytes());
+    shared.extend(0u16.to_le_bytes());
+    shared
+}
+
+fn central(flags: u16, crc: u32, size: u32, offset: u32, name: &[u8]) -> Vec<u8> {
+    let mut entry = CENTRAL.to_le_bytes().to_vec();
+    entry.extend(VERSION.to_le_bytes());
+    entry.extend(shared(flags, crc, size, name));
+    // comment length, disk, internal and external attributes
+    for field in [0u16, 0, 0] { entry.extend(field.to_le_bytes()) }
+    entry.extend(0u32.to_le_bytes());
+    entry.extend(offset.to_le_bytes());
+    entry.extend(name);
+    entry
+}
+
+fn end(start: u32, size: u32, count: u16) -> Vec<u8> {
+    let mut end = END.to_le_bytes().to_vec();
+    for field in [0u16, 0, count, count] { end.extend(field.to_le_bytes()) }
+    end.extend(size.to_le_bytes());
+    end.extend(start.to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    end
+}
+
+// The archive of the files, by name. Names should be unique, with / between folders.
+pub fn zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut archive = vec![];
+    let mut directory = vec![];
+    for (name, bytes) in files {
+        let offset = archive.len() as u32;
+        let (crc, size, name) = (crc32(bytes), bytes.len() as u32, name.as_bytes());
+        archive.extend(LOCAL.to_le_bytes());
+        archive.extend(shared(UTF8_NAMES, crc, size, name));
+        archive.extend(name);
+        archive.extend(bytes);
+        directory.extend(central(UTF8_NAMES, crc, size, offset, name));
+    }
+
+    let (start, size, count) = (archive.len() as u32, directory.len() as u32, files.len() as u16);
+    archive.extend(directory);
+    archive.extend(end(start, size, count));
+    archive
+}
+
+// An archive written a piece at a time: each method gives the bytes which come next.
+// Files are written one after another, start, then their contents in as many pieces as they come, then the next start or finish.
+#[derive(Debug, Default)]
+pub struct Stream {
+    // bytes given out so far
+    written: u32,
+    directory: Vec<u8>,
+    count: u16,
+    // the file being written: its name, where its header is, its crc so far and size
+    current: Option<(String, u32, u32, u32)>,
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream::default()
+    }
+
+    fn give(&mut self, bytes: Vec<u8>) -> Vec<u8> {
+        self.written += bytes.len() as u32;
+        bytes
+    }
+
+    // the end of the file being written, its crc and size now they're known
+    fn close(&mut self) -> Vec<u8> {
+        let (name, offset, crc, size) = match self.current.take() {
+            Some(current) => current,
+            None => return vec![],
+        };
+        let crc = !crc;
+        let mut descriptor = DESCRIPTOR_SIGNATURE.to_le_bytes().to_vec();
+        for field in [crc, size, size] { descriptor.extend(field.to_le_bytes()) }
+        self.directory.extend(central(UTF8_NAMES | DESCRIPTOR, crc, size, offset, name.as_bytes()));
+        self.count += 1;
+        descriptor
+    }
+
+    pub fn start(&mut self, name: &str) -> Vec<u8> {
+        let mut bytes = self.close();
+        let offset = self.written + bytes.len() as u32;
+        bytes.extend(LOCAL.to_le_bytes());
+        bytes.extend(shared(UTF8_NAMES | DESCRIPTOR, 0, 0, name.as_bytes()));
+        bytes.extend(name.as_bytes());
+        self.current = Some((name.to_string(), offset, !0, 0));
+        self.give(bytes)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> Vec<u8> {
+        if let Some((_, _, crc, size)) = &mut self.current {
+            *crc = update(*crc, bytes);
+            *size += bytes.len() as u32;
+        }
+        self.give(bytes.to_vec())
+    }
+
+    // a whole file at once
+    pub fn file(&mut self, name: &str, bytes: &[u8]) -> Vec<u8> {
+        let mut out = self.start(name);
+        out.extend(self.write(bytes));
+        out
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let mut bytes = self.close();
+        let start = self.written + bytes.len() as u32;
+        bytes.extend(&self.directory);
+        bytes.extend(end(start, self.directory.len() as u32, self.count));
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ crc32, zip, Stream };
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn archives() {
+        let archive = zip(&[("9A/Sam.pdf".to_string(), b"%PDF".to_vec()), ("9A/Zoë.pdf".to_string(), vec![])]);
+        assert_eq!(&archive[..4], b"PK\x03\x04");
+        // the first file's name and contents follow its 30 byte header
+        assert_eq!(&archive[30..44], b"9A/Sam.pdf%PDF");
+
+        let end = archive.len() - 22;
+        assert_eq!(&archive[end..end + 4], b"PK\x05\x06");
+        assert_eq!(u16::from_le_bytes([archive[end + 10], archive[end + 11]]), 2);
+        let directory = u32::from_le_bytes(archive[end + 16..end + 20].try_into().unwrap()) as usize;
+        assert_eq!(&archive[directory..directory + 4], b"PK\x01\x02");
+        // the second entry points back at the second file's header
+        let second = directory + 46 + "9A/Sam.pdf".len();
+        let offset = u32::from_le_bytes(archive[second + 42..second + 46].try_into().unwrap()) as usize;
+        assert_eq!(&archive[offset..offset + 4], b"PK\x03\x04");
+        assert_eq!(offset, 30 + 10 + 4);
+    }
+
+    #[test]
+    fn streamed_archives_match_their_directory() {
+        let mut stream = Stream::new();
+        let mut archive = stream.start("a.csv");
+        archive.extend(stream.write(b"one,"));
+        archive.extend(stream.write(b"two"));
+        archive.extend(stream.file("b.txt", b"three"));
+        archive.extend(stream.finish());
+
+        // a.csv's header, contents and descriptor, whose crc covers both pieces
+        assert_eq!(&archive[30..35], b"a.csv");
+        assert_eq!(&archive[35..42], b"one,two");
+        assert_eq!(&archive[42..46], b"PK\x07\x08");
+        assert_eq!(u32::from_le_bytes(archive[46..50].try_into().unwrap()), crc32(b"one,two"));
+
+        let end = archive.len() - 22;
+        assert_eq!(u16::from_le_bytes([archive[end + 10], archive[end + 11]]), 2);
+        let directory = u32::from_le_bytes(archive[end + 16..end + 20].try_into().unwrap()) as usize;
+        let second = directory + 46 + "a.csv".len();
+        assert_eq!(u32::from_le_bytes(archive[second + 16..second + 20].try_into().unwrap()), crc32(b"three"));
+        let offset = u32::from_le_bytes(archive[second + 42..second + 46].try_into().unwrap()) as usize;
+        assert_eq!(&archive[offset + 30..offset + 35], b"b.txt");
+    }
+}